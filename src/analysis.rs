@@ -0,0 +1,204 @@
+//! Loudness analysis for ReplayGain, following ReplayGain 2.0: the EBU R128 integrated loudness of
+//! the audio is measured and compared against a reference of -18 LUFS. Only uncompressed WAV files
+//! can be decoded, and only mono and stereo ones are measured.
+
+use std::convert::TryInto;
+use std::f64::consts::PI;
+use std::fs;
+
+/// The loudness ReplayGain 2.0 brings tracks to, in LUFS
+const REFERENCE: f64 = -18.0;
+
+/// The measurements of a track, kept so tracks can be combined into an album
+pub struct Loudness {
+    /// The mean square of each 400ms block, after K-weighting and summed over the channels
+    blocks: Vec<f64>,
+    /// The largest sample, where 1.0 is full scale
+    pub peak: f64,
+}
+
+impl Loudness {
+    /// The gain in dB that brings the audio to the reference loudness. `None` when the audio is
+    /// too short or too quiet to measure.
+    pub fn gain(&self) -> Option<f64> {
+        integrated(&self.blocks).map(|l| REFERENCE - l)
+    }
+
+    /// Combines tracks into an album, as if they were played one after another
+    pub fn album(tracks: &[Loudness]) -> Loudness {
+        Loudness {
+            blocks: tracks.iter().flat_map(|t| t.blocks.iter().copied()).collect(),
+            peak: tracks.iter().map(|t| t.peak).fold(0.0, f64::max),
+        }
+    }
+}
+
+/// A second order IIR filter
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// The two stages of the K-weighting filter, worked out for the sample rate as libebur128 does
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // The high shelf, modelling the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(g / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // The high pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn block_loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// The integrated loudness of a set of blocks in LUFS, gated as EBU R128 describes
+fn integrated(blocks: &[f64]) -> Option<f64> {
+    let gated_mean = |gate: f64| {
+        let kept: Vec<f64> = blocks.iter().copied().filter(|&b| block_loudness(b) > gate).collect();
+
+        if kept.is_empty() {
+            None
+        } else {
+            Some(kept.iter().sum::<f64>() / kept.len() as f64)
+        }
+    };
+
+    let absolute = gated_mean(-70.0)?;
+    let relative = gated_mean(block_loudness(absolute) - 10.0)?;
+
+    Some(block_loudness(relative))
+}
+
+/// The samples of a WAV file, with each channel interleaved and scaled to -1.0..=1.0
+struct Pcm {
+    samples: Vec<f64>,
+    channels: usize,
+    sample_rate: u32,
+}
+
+fn read_wav(data: &[u8]) -> Option<Pcm> {
+    if !data.starts_with(b"RIFF") || data.get(8..12) != Some(b"WAVE") {
+        return None;
+    }
+
+    let u16_le = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?));
+    let u32_le = |at: usize| Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?));
+
+    let mut format = None;
+    let mut pos = 12;
+
+    while let (Some(id), Some(size)) = (data.get(pos..pos + 4), u32_le(pos + 4)) {
+        let body = pos + 8;
+        let size = size as usize;
+
+        match id {
+            b"fmt " => {
+                // The format tag, channels, sample rate, and bits per sample
+                format = Some((u16_le(body)?, u16_le(body + 2)?, u32_le(body + 4)?, u16_le(body + 14)?));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, bits) = format?;
+                let body = data.get(body..body + size).or_else(|| data.get(body..))?;
+
+                // 1 is integer PCM, 3 is floating point, and 0xFFFE defers to the extension
+                let samples: Vec<f64> = match (tag, bits) {
+                    (1, 16) | (0xFFFE, 16) => body.chunks_exact(2)
+                        .map(|s| f64::from(i16::from_le_bytes([s[0], s[1]])) / 32768.0)
+                        .collect(),
+                    (1, 24) | (0xFFFE, 24) => body.chunks_exact(3)
+                        .map(|s| f64::from(i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) / 8_388_608.0)
+                        .collect(),
+                    (1, 32) => body.chunks_exact(4)
+                        .map(|s| f64::from(i32::from_le_bytes([s[0], s[1], s[2], s[3]])) / 2_147_483_648.0)
+                        .collect(),
+                    (3, 32) | (0xFFFE, 32) => body.chunks_exact(4)
+                        .map(|s| f64::from(f32::from_le_bytes([s[0], s[1], s[2], s[3]])))
+                        .collect(),
+                    _ => return None,
+                };
+
+                return Some(Pcm { samples, channels: usize::from(channels), sample_rate });
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even length
+        pos = body + size + (size & 1);
+    }
+
+    None
+}
+
+/// Measures the loudness of the file at `path`. `None` when the file can't be decoded, or has
+/// something other than one or two channels.
+pub fn analyze(path: &str) -> Option<Loudness> {
+    let pcm = read_wav(&fs::read(path).ok()?)?;
+
+    if pcm.channels != 1 && pcm.channels != 2 || pcm.sample_rate == 0 {
+        return None;
+    }
+
+    let mut filters: Vec<[Biquad; 2]> = (0..pcm.channels).map(|_| k_weighting(f64::from(pcm.sample_rate))).collect();
+
+    // Blocks are 400ms long and start every 100ms, so each is made up of four steps
+    let step = (pcm.sample_rate / 10) as usize;
+    let mut steps: Vec<f64> = Vec::new();
+    let mut sum = 0.0;
+    let mut peak: f64 = 0.0;
+
+    for (i, frame) in pcm.samples.chunks_exact(pcm.channels).enumerate() {
+        for (sample, [shelf, high_pass]) in frame.iter().zip(&mut filters) {
+            peak = peak.max(sample.abs());
+
+            let weighted = high_pass.process(shelf.process(*sample));
+            sum += weighted * weighted;
+        }
+
+        if (i + 1) % step == 0 {
+            steps.push(sum);
+            sum = 0.0;
+        }
+    }
+
+    let blocks = steps.windows(4)
+        .map(|w| w.iter().sum::<f64>() / (4 * step) as f64)
+        .collect();
+
+    Some(Loudness { blocks, peak })
+}
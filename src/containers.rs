@@ -0,0 +1,105 @@
+//! Reads a field from each tag container in a file on its own. lofty only reads the tag it picks for
+//! the file's format, so when an MP3 also has an ID3v1 or APEv2 tag, the values in those can't be
+//! seen through it. The ID3v2 tag is only found at the start of the file, as in an MP3.
+
+use std::path::Path;
+
+/// How many characters an ID3v1 text field holds
+const ID3V1_TEXT_LEN: usize = 30;
+
+/// The value of `field`, named as `field_to_str` does, in each container of the file at `path`
+/// that both exists and can hold the field. A container that can hold the field but doesn't have
+/// it gives `None`.
+pub fn values(path: &Path, field: &str) -> Vec<(&'static str, Option<String>)> {
+    let mut values = Vec::new();
+
+    if let Ok(tag) = id3::Tag::read_from_path(path) {
+        values.push(("ID3v2", id3v2_value(&tag, field)));
+    }
+
+    if let Ok(tag) = id3::v1::Tag::read_from_path(path) {
+        if let Some(value) = id3v1_value(&tag, field) {
+            values.push(("ID3v1", value));
+        }
+    }
+
+    if let Ok(tag) = ape::read(path) {
+        values.push(("APEv2", ape_value(&tag, field)));
+    }
+
+    values
+}
+
+fn id3v2_value(tag: &id3::Tag, field: &str) -> Option<String> {
+    match field {
+        "track" => tag.track().map(|n| n.to_string()),
+        "year" => tag.year().map(|n| n.to_string()),
+        "disc" => tag.disc().map(|n| n.to_string()),
+        "title" => tag.title().map(String::from),
+        "artist" => tag.artist().map(String::from),
+        "album" => tag.album().map(String::from),
+        "albumartist" => tag.album_artist().map(String::from),
+        "genre" => tag.genre().map(String::from),
+        "composer" => tag.get("TCOM").and_then(|f| f.content().text()).map(String::from),
+        "comment" => tag.comments().find(|c| c.description.is_empty()).map(|c| c.text.clone()),
+        "image" => tag.pictures().next().map(|_| "Present".to_string()),
+        _ => None,
+    }
+}
+
+/// `None` when ID3v1 has no room for the field
+fn id3v1_value(tag: &id3::v1::Tag, field: &str) -> Option<Option<String>> {
+    let text = |s: &str| Some(s.trim_end_matches(['\0', ' '])).filter(|s| !s.is_empty()).map(String::from);
+
+    match field {
+        "track" => Some(tag.track.filter(|n| *n != 0).map(|n| n.to_string())),
+        "year" => Some(text(&tag.year)),
+        "title" => Some(text(&tag.title)),
+        "artist" => Some(text(&tag.artist)),
+        "album" => Some(text(&tag.album)),
+        "genre" => Some(tag.genre().map(String::from)),
+        "comment" => Some(text(&tag.comment)),
+        _ => None,
+    }
+}
+
+fn ape_value(tag: &ape::Tag, field: &str) -> Option<String> {
+    let key = match field {
+        "track" => "Track",
+        "year" => "Year",
+        "disc" => "Disc",
+        "title" => "Title",
+        "artist" => "Artist",
+        "album" => "Album",
+        "albumartist" => "Album Artist",
+        "genre" => "Genre",
+        "composer" => "Composer",
+        "comment" => "Comment",
+        "image" => return tag.item("Cover Art (Front)").map(|_| "Present".to_string()),
+        _ => return None,
+    };
+
+    match tag.item(key).map(|i| &i.value) {
+        // Track and disc numbers can be stored with the total, like `3/12`
+        Some(ape::ItemValue::Text(s)) if field == "track" || field == "disc" => s.split('/').next().map(|n| n.trim().to_string()),
+        Some(ape::ItemValue::Text(s)) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Whether the values of the containers disagree. Values are compared ignoring surrounding
+/// whitespace, and an ID3v1 value that's been cut short to fit still agrees with the full value.
+pub fn disagree(values: &[(&'static str, Option<String>)]) -> bool {
+    let agree = |a: &(&str, Option<String>), b: &(&str, Option<String>)| match (&a.1, &b.1) {
+        (Some(x), Some(y)) => {
+            let (x, y) = (x.trim(), y.trim());
+            let cut = |s: &str| s.chars().take(ID3V1_TEXT_LEN).collect::<String>();
+
+            x == y || (a.0 == "ID3v1" && x == cut(y).trim_end()) || (b.0 == "ID3v1" && y == cut(x).trim_end())
+        }
+        (None, None) => true,
+        _ => false,
+    };
+
+    values.iter().any(|a| values.iter().any(|b| !agree(a, b)))
+}
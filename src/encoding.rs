@@ -0,0 +1,189 @@
+//! Reads the text encoding of each frame in an ID3v2 tag. The id3 crate decodes every frame into a
+//! `String` and forgets the encoding it was stored in, and merges frames that repeat, so the tag's
+//! frames are walked here directly.
+
+use std::convert::TryInto;
+
+/// The text encodings an ID3v2 frame can be stored in
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    Latin1,
+    Utf16,
+    Utf16Be,
+    Utf8,
+}
+
+impl TextEncoding {
+    fn from_byte(b: u8) -> Option<TextEncoding> {
+        match b {
+            0 => Some(TextEncoding::Latin1),
+            1 => Some(TextEncoding::Utf16),
+            2 => Some(TextEncoding::Utf16Be),
+            3 => Some(TextEncoding::Utf8),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Latin1 => "Latin-1",
+            TextEncoding::Utf16 => "UTF-16",
+            TextEncoding::Utf16Be => "UTF-16BE",
+            TextEncoding::Utf8 => "UTF-8",
+        }
+    }
+}
+
+fn synchsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, b| (acc << 7) | usize::from(b & 0x7F))
+}
+
+/// Finds the ID3v2 tag in a file: at the start of an MP3, or in the `ID3 ` chunk of a WAV or AIFF
+fn find_tag(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(b"ID3") {
+        return Some(data);
+    }
+
+    let big_endian = if data.starts_with(b"RIFF") {
+        false
+    } else if data.starts_with(b"FORM") {
+        true
+    } else {
+        return None;
+    };
+
+    let mut pos = 12;
+
+    while let Some(id) = data.get(pos..pos + 4) {
+        let size: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let size = if big_endian { u32::from_be_bytes(size) } else { u32::from_le_bytes(size) } as usize;
+
+        if id.eq_ignore_ascii_case(b"ID3 ") {
+            return data.get(pos + 8..);
+        }
+
+        // Chunks are padded to an even length
+        pos += 8 + size + (size & 1);
+    }
+
+    None
+}
+
+/// Whether frames with this ID start with an encoding byte
+fn has_encoding(id: &str) -> bool {
+    id.starts_with('T') || id.starts_with("WXX") || matches!(id, "COMM" | "COM" | "USLT" | "ULT" | "APIC" | "PIC" | "GEOB" | "GEO" | "SYLT" | "SLT")
+}
+
+/// The ID and body of every frame in a file's ID3v2 tag, in the order they're stored. `None` when
+/// the file has no ID3v2 tag, or one that can't be walked.
+fn frames(data: &[u8]) -> Option<Vec<(String, &[u8])>> {
+    let tag = find_tag(data)?;
+    let header = tag.get(..10)?;
+
+    let version = header[3];
+    let unsynchronised = header[5] & 0x80 != 0;
+    let end = (10 + synchsafe(&header[6..10])).min(tag.len());
+
+    // Unsynchronised tags would need every frame undone before it could be read
+    if unsynchronised || !(2..=4).contains(&version) {
+        return None;
+    }
+
+    let mut pos = 10;
+
+    // Skip over an extended header
+    if header[5] & 0x40 != 0 && version != 2 {
+        let size = tag.get(10..14)?;
+        pos += if version == 4 { synchsafe(size) } else { 4 + u32::from_be_bytes(size.try_into().ok()?) as usize };
+    }
+
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut frames = Vec::new();
+
+    while pos + header_len <= end {
+        let id = &tag[pos..pos + id_len];
+
+        // The rest of the tag is padding
+        if id[0] == 0 {
+            break;
+        }
+
+        let size = match version {
+            2 => tag[pos + 3..pos + 6].iter().fold(0, |acc, b| (acc << 8) | usize::from(*b)),
+            3 => u32::from_be_bytes(tag[pos + 4..pos + 8].try_into().ok()?) as usize,
+            _ => synchsafe(&tag[pos + 4..pos + 8]),
+        };
+
+        let id = String::from_utf8_lossy(id).into_owned();
+        let mut body = pos + header_len;
+        let next = (body + size).min(end);
+
+        // ID3v2.4 frames can put their original length in front of the body
+        if version == 4 && tag[pos + 9] & 0x01 != 0 {
+            body += 4;
+        }
+
+        frames.push((id, tag.get(body..next).unwrap_or_default()));
+        pos = next;
+    }
+
+    Some(frames)
+}
+
+/// The ID and encoding of every frame that holds text, in the order they're stored. `None` when the
+/// file has no ID3v2 tag, or one that can't be walked.
+pub fn frame_encodings(data: &[u8]) -> Option<Vec<(String, TextEncoding)>> {
+    let frames = frames(data)?;
+
+    Some(frames.into_iter()
+        .filter(|(id, _)| has_encoding(id))
+        .filter_map(|(id, body)| Some((id, body.first().copied().and_then(TextEncoding::from_byte)?)))
+        .collect())
+}
+
+/// Decodes text up to the first null in `encoding`, or all of it when there's no null
+fn decode_until_null(encoding: &TextEncoding, bytes: &[u8]) -> String {
+    match encoding {
+        TextEncoding::Latin1 => bytes.iter().take_while(|b| **b != 0).map(|b| char::from(*b)).collect(),
+        TextEncoding::Utf8 => {
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        }
+        TextEncoding::Utf16 | TextEncoding::Utf16Be => {
+            let (big_endian, bytes) = match bytes {
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                _ => (*encoding == TextEncoding::Utf16Be, bytes),
+            };
+
+            let units: Vec<u16> = bytes.chunks_exact(2)
+                .map(|c| if big_endian { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) })
+                .take_while(|u| *u != 0)
+                .collect();
+
+            String::from_utf16_lossy(&units)
+        }
+    }
+}
+
+/// The key of every frame, in the order they're stored. Frames that can be told apart by a
+/// description, like `TXXX` and `COMM`, have it added after a `:` as `inner::all` does. Unlike the
+/// id3 crate, frames that repeat aren't merged into one. `None` when the file has no ID3v2 tag, or
+/// one that can't be walked.
+pub fn frame_keys(data: &[u8]) -> Option<Vec<String>> {
+    let frames = frames(data)?;
+
+    Some(frames.into_iter().map(|(id, body)| {
+        // How far into the body the description starts, after the encoding and any language
+        let start = match id.as_str() {
+            "TXXX" | "WXXX" | "TXX" | "WXX" => 1,
+            "COMM" | "USLT" | "COM" | "ULT" => 4,
+            _ => return id,
+        };
+
+        match (body.first().copied().and_then(TextEncoding::from_byte), body.get(start..)) {
+            (Some(encoding), Some(rest)) => format!("{}:{}", id, decode_until_null(&encoding, rest)),
+            _ => id,
+        }
+    }).collect())
+}
@@ -0,0 +1,725 @@
+//! Access to the tags lofty wraps, for the parts of a tag that `AudioTag` doesn't cover. The ID3v2
+//! (MP3, WAV, AIFF), MP4, and APE tags can be reached directly. lofty keeps the Vorbis comments
+//! private, but converts them to and from a `metaflac::Tag`, so the fields that are needed are
+//! reached that way for FLAC files. Ogg and Opus files can't be, since the conversion always marks
+//! the tag as FLAC, which would then be written to them as FLAC metadata blocks.
+
+use std::convert::TryFrom;
+use std::any::Any;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use lofty::{
+    Album, AnyTag, ApeTag, AudioTag, AudioTagEdit, AudioTagWrite, Id3v2Tag, Mp4Tag, Picture, Tag, ToAny, ToAnyTag,
+    VorbisFormat, VorbisTag,
+};
+
+/// Reads the tag of the file at `path`. lofty reads the whole file to find its signature, and then
+/// reads an MP3 again to work out its duration, none of which is needed for the tag. So FLAC files
+/// and files starting with an ID3v2 tag have only their tag read, and everything else falls back to
+/// lofty. See the README for how much this saves on large files.
+pub fn read(path: &str) -> lofty::Result<Box<dyn AudioTag>> {
+    let mut magic = [0; 4];
+    let magic_len = File::open(path)?.read(&mut magic)?;
+
+    match &magic[..magic_len] {
+        b"fLaC" => Ok(Box::new(FlacTag(VorbisTag::read_from_path(path, VorbisFormat::Flac)?))),
+        [b'I', b'D', b'3', _] => Ok(Box::new(Id3v2Tag::from(id3::Tag::read_from_path(path)?))),
+        // lofty can't open a WAV file without an INFO or ID3 chunk, which is most of them, so those
+        // start out with an empty ID3v2 tag that's written as a new chunk
+        b"RIFF" => Tag::new().read_from_path_signature(path).or_else(|e| match id3::Tag::read_from_wav(path) {
+            Err(id3::Error { kind: id3::ErrorKind::NoTag, .. }) => Ok(Box::new(Id3v2Tag::from(id3::Tag::new()))),
+            _ => Err(e),
+        }),
+        _ => Tag::new().read_from_path_signature(path),
+    }
+}
+
+/// The Vorbis comments of a FLAC file. lofty's `VorbisTag` doesn't say which kind of file it came
+/// from, so this marks the ones that can be converted to a `metaflac::Tag` and back. Everything
+/// else is passed through to the `VorbisTag`.
+pub struct FlacTag(VorbisTag);
+
+impl AudioTagEdit for FlacTag {
+    fn title(&self) -> Option<&str> {
+        self.0.title()
+    }
+    fn set_title(&mut self, title: &str) {
+        self.0.set_title(title)
+    }
+    fn remove_title(&mut self) {
+        self.0.remove_title()
+    }
+
+    fn artist_str(&self) -> Option<&str> {
+        self.0.artist_str()
+    }
+    fn set_artist(&mut self, artist: &str) {
+        self.0.set_artist(artist)
+    }
+    fn artists_vec(&self) -> Option<Vec<&str>> {
+        self.0.artists_vec()
+    }
+    fn remove_artist(&mut self) {
+        self.0.remove_artist()
+    }
+
+    fn year(&self) -> Option<i32> {
+        self.0.year()
+    }
+    fn set_year(&mut self, year: i32) {
+        self.0.set_year(year)
+    }
+    fn remove_year(&mut self) {
+        self.0.remove_year()
+    }
+
+    fn album(&self) -> Album<'_> {
+        self.0.album()
+    }
+    fn album_title(&self) -> Option<&str> {
+        self.0.album_title()
+    }
+    fn set_album_title(&mut self, v: &str) {
+        self.0.set_album_title(v)
+    }
+    fn remove_album_title(&mut self) {
+        self.0.remove_album_title()
+    }
+
+    fn album_artist_str(&self) -> Option<&str> {
+        self.0.album_artist_str()
+    }
+    fn album_artists_vec(&self) -> Option<Vec<&str>> {
+        self.0.album_artists_vec()
+    }
+    fn set_album_artist(&mut self, artist: &str) {
+        self.0.set_album_artist(artist)
+    }
+    fn remove_album_artists(&mut self) {
+        self.0.remove_album_artists()
+    }
+
+    fn album_cover(&self) -> Option<Picture<'_>> {
+        self.0.album_cover()
+    }
+    fn set_album_cover(&mut self, cover: Picture) {
+        self.0.set_album_cover(cover)
+    }
+    fn remove_album_cover(&mut self) {
+        self.0.remove_album_cover()
+    }
+
+    fn track_number(&self) -> Option<u32> {
+        self.0.track_number()
+    }
+    fn set_track_number(&mut self, track_number: u32) {
+        self.0.set_track_number(track_number)
+    }
+    fn remove_track_number(&mut self) {
+        self.0.remove_track_number()
+    }
+
+    fn total_tracks(&self) -> Option<u32> {
+        self.0.total_tracks()
+    }
+    fn set_total_tracks(&mut self, total_tracks: u32) {
+        self.0.set_total_tracks(total_tracks)
+    }
+    fn remove_total_tracks(&mut self) {
+        self.0.remove_total_tracks()
+    }
+
+    fn disc_number(&self) -> Option<u32> {
+        self.0.disc_number()
+    }
+    fn set_disc_number(&mut self, disc_number: u32) {
+        self.0.set_disc_number(disc_number)
+    }
+    fn remove_disc_number(&mut self) {
+        self.0.remove_disc_number()
+    }
+
+    fn total_discs(&self) -> Option<u32> {
+        self.0.total_discs()
+    }
+    fn set_total_discs(&mut self, total_discs: u32) {
+        self.0.set_total_discs(total_discs)
+    }
+    fn remove_total_discs(&mut self) {
+        self.0.remove_total_discs()
+    }
+}
+
+// lofty writes a FLAC file's comments in place of all of its metadata blocks, STREAMINFO included,
+// which leaves the file unplayable. So only the VORBIS_COMMENT block is swapped out here, and the
+// other blocks and the audio are written back as they were.
+impl AudioTagWrite for FlacTag {
+    fn write_to(&self, file: &mut File) -> lofty::Result<()> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut tag = metaflac::Tag::read_from(file)?;
+
+        file.seek(SeekFrom::Start(0))?;
+        let audio = metaflac::Tag::skip_metadata(file);
+
+        tag.remove_blocks(metaflac::BlockType::VorbisComment);
+        if let Some(comments) = metaflac::Tag::from(&self.0).vorbis_comments() {
+            tag.push_block(metaflac::Block::VorbisComment(comments.clone()));
+        }
+
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        tag.write_to(file)?;
+        file.write_all(&audio)?;
+
+        Ok(())
+    }
+    fn write_to_path(&self, path: &str) -> lofty::Result<()> {
+        self.write_to(&mut OpenOptions::new().read(true).write(true).open(path)?)
+    }
+}
+
+impl ToAnyTag for FlacTag {
+    fn to_anytag(&self) -> AnyTag<'_> {
+        self.0.to_anytag()
+    }
+}
+
+impl ToAny for FlacTag {
+    fn to_any(&self) -> &dyn Any {
+        self
+    }
+    fn to_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl AudioTag for FlacTag {}
+
+/// The file's tag format doesn't give us a way to reach the field
+#[derive(Debug)]
+pub struct Unsupported;
+
+/// Runs the closure matching the type of tag that `tag` wraps. The inner tag is taken out of
+/// lofty's wrapper for the duration of the call and put back afterwards.
+fn dispatch<R>(
+    tag: &mut dyn AudioTag,
+    id3: impl FnOnce(&mut id3::Tag) -> R,
+    mp4: impl FnOnce(&mut mp4ameta::Tag) -> R,
+    ape: impl FnOnce(&mut ape::Tag) -> R,
+) -> Result<R, Unsupported> {
+    let any = tag.to_any_mut();
+
+    if let Some(wrapper) = any.downcast_mut::<Id3v2Tag>() {
+        let mut inner: id3::Tag = mem::take(wrapper).into();
+        let result = id3(&mut inner);
+        *wrapper = inner.into();
+        Ok(result)
+    } else if let Some(wrapper) = any.downcast_mut::<Mp4Tag>() {
+        let mut inner: mp4ameta::Tag = mem::take(wrapper).into();
+        let result = mp4(&mut inner);
+        *wrapper = inner.into();
+        Ok(result)
+    } else if let Some(wrapper) = any.downcast_mut::<ApeTag>() {
+        let mut inner: ape::Tag = mem::take(wrapper).into();
+        let result = ape(&mut inner);
+        *wrapper = inner.into();
+        Ok(result)
+    } else {
+        Err(Unsupported)
+    }
+}
+
+/// Like `dispatch`, with a closure for the Vorbis comments of FLAC files as well. The comments are
+/// converted to a `metaflac::Tag` for the call and back again afterwards.
+fn dispatch_with_flac<R>(
+    tag: &mut dyn AudioTag,
+    id3: impl FnOnce(&mut id3::Tag) -> R,
+    mp4: impl FnOnce(&mut mp4ameta::Tag) -> R,
+    ape: impl FnOnce(&mut ape::Tag) -> R,
+    flac: impl FnOnce(&mut metaflac::Tag) -> R,
+) -> Result<R, Unsupported> {
+    if let Some(FlacTag(wrapper)) = tag.to_any_mut().downcast_mut::<FlacTag>() {
+        let mut inner = metaflac::Tag::from(&*wrapper);
+        let result = flac(&mut inner);
+        *wrapper = inner.into();
+        Ok(result)
+    } else {
+        dispatch(tag, id3, mp4, ape)
+    }
+}
+
+fn flac_text(tag: &metaflac::Tag, key: &str) -> Option<String> {
+    tag.get_vorbis(key).and_then(|mut values| values.next()).map(String::from)
+}
+
+fn ape_text(tag: &ape::Tag, key: &str) -> Option<String> {
+    match tag.item(key).map(|i| &i.value) {
+        Some(ape::ItemValue::Text(s)) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn set_ape_text(tag: &mut ape::Tag, key: &str, value: &str) {
+    if let Ok(item) = ape::Item::from_text(key, value) {
+        tag.set_item(item);
+    }
+}
+
+pub fn genre(tag: &mut dyn AudioTag) -> Result<Option<String>, Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.genre().map(String::from),
+        |t| t.genre().map(String::from),
+        |t| ape_text(t, "Genre"),
+        |t| flac_text(t, "GENRE"),
+    )
+}
+
+pub fn set_genre(tag: &mut dyn AudioTag, genre: &str) -> Result<(), Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.set_genre(genre),
+        |t| t.set_genre(genre),
+        |t| set_ape_text(t, "Genre", genre),
+        |t| t.set_vorbis("GENRE", vec![genre]),
+    )
+}
+
+pub fn remove_genre(tag: &mut dyn AudioTag) -> Result<(), Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.remove_genre(),
+        |t| {
+            t.remove_standard_genres();
+            t.remove_custom_genres();
+        },
+        |t| { t.remove_item("Genre"); },
+        |t| t.remove_vorbis("GENRE"),
+    )
+}
+
+pub fn composer(tag: &mut dyn AudioTag) -> Result<Option<String>, Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.get("TCOM").and_then(|f| f.content().text()).map(String::from),
+        |t| t.composer().map(String::from),
+        |t| ape_text(t, "Composer"),
+        |t| flac_text(t, "COMPOSER"),
+    )
+}
+
+pub fn set_composer(tag: &mut dyn AudioTag, composer: &str) -> Result<(), Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.set_text("TCOM", composer),
+        |t| t.set_composer(composer),
+        |t| set_ape_text(t, "Composer", composer),
+        |t| t.set_vorbis("COMPOSER", vec![composer]),
+    )
+}
+
+pub fn remove_composer(tag: &mut dyn AudioTag) -> Result<(), Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| { t.remove("TCOM"); },
+        |t| t.remove_composers(),
+        |t| { t.remove_item("Composer"); },
+        |t| t.remove_vorbis("COMPOSER"),
+    )
+}
+
+/// Sets the flag that marks a track as part of a compilation
+pub fn set_compilation(tag: &mut dyn AudioTag) -> Result<(), Unsupported> {
+    dispatch(
+        tag,
+        |t| t.set_text("TCMP", "1"),
+        |t| t.set_compilation(),
+        |t| set_ape_text(t, "Compilation", "1"),
+    )
+}
+
+/// The comment without a description, which is the one players show
+pub fn comment(tag: &mut dyn AudioTag) -> Result<Option<String>, Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.comments().find(|c| c.description.is_empty()).map(|c| c.text.clone()),
+        |t| t.comment().map(String::from),
+        |t| ape_text(t, "Comment"),
+        |t| flac_text(t, "COMMENT"),
+    )
+}
+
+pub fn set_comment(tag: &mut dyn AudioTag, comment: &str) -> Result<(), Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| {
+            t.remove_comment(Some(""), None);
+            t.add_comment(id3::frame::Comment {
+                lang: "eng".to_string(),
+                description: String::new(),
+                text: comment.to_string(),
+            });
+        },
+        |t| t.set_comment(comment),
+        |t| set_ape_text(t, "Comment", comment),
+        |t| t.set_vorbis("COMMENT", vec![comment]),
+    )
+}
+
+pub fn remove_comment(tag: &mut dyn AudioTag) -> Result<(), Unsupported> {
+    dispatch_with_flac(
+        tag,
+        |t| t.remove_comment(Some(""), None),
+        |t| t.remove_comments(),
+        |t| { t.remove_item("Comment"); },
+        |t| t.remove_vorbis("COMMENT"),
+    )
+}
+
+fn id3_cover(tag: &id3::Tag) -> Option<&id3::frame::Picture> {
+    tag.pictures().find(|p| p.picture_type == id3::frame::PictureType::CoverFront)
+}
+
+/// The description of the front cover. Only ID3v2 tags keep one, the others always give `None`
+pub fn cover_description(tag: &mut dyn AudioTag) -> Result<Option<String>, Unsupported> {
+    dispatch(
+        tag,
+        |t| id3_cover(t).map(|p| p.description.clone()),
+        |_| None,
+        |_| None,
+    )
+}
+
+/// Sets the description of the front cover, if there is one. Does nothing outside of ID3v2 tags
+pub fn set_cover_description(tag: &mut dyn AudioTag, description: &str) -> Result<(), Unsupported> {
+    dispatch(
+        tag,
+        |t| {
+            if let Some(mut picture) = id3_cover(t).cloned() {
+                picture.description = description.to_string();
+                t.remove_picture_by_type(id3::frame::PictureType::CoverFront);
+                t.add_picture(picture);
+            }
+        },
+        |_| (),
+        |_| (),
+    )
+}
+
+/// Writes an ID3v2 tag to `path` in the given version, since lofty always writes ID3v2.4. Returns
+/// whether the write succeeded.
+pub fn write_id3(tag: &mut dyn AudioTag, path: &str, version: id3::Version) -> Result<bool, Unsupported> {
+    let written = dispatch(
+        tag,
+        |t| {
+            let result = match fs::read(path).ok().as_ref().and_then(|d| d.get(0..4)) {
+                Some(b"RIFF") => t.write_to_wav(path, version),
+                Some(b"FORM") => t.write_to_aiff(path, version),
+                _ => t.write_to_path(path, version),
+            };
+            Some(result.is_ok())
+        },
+        |_| None,
+        |_| None,
+    )?;
+
+    written.ok_or(Unsupported)
+}
+
+/// An MP4 atom identifier from a key. Four character keys are atom names, with characters like `©`
+/// taken as the single byte they stand for. Anything else names an iTunes freeform atom.
+fn mp4_ident(key: &str) -> mp4ameta::DataIdent {
+    let bytes: Option<Vec<u8>> = key.chars().map(|c| u8::try_from(u32::from(c)).ok()).collect();
+
+    match bytes.as_deref().map(<[u8; 4]>::try_from) {
+        Some(Ok(fourcc)) => mp4ameta::DataIdent::FourCC(mp4ameta::FourCC(fourcc)),
+        _ => mp4ameta::DataIdent::Freeform { mean: "com.apple.iTunes".to_string(), name: key.to_string() },
+    }
+}
+
+/// The text of an arbitrary key: a frame ID or `TXXX` description for ID3v2, an atom name or
+/// freeform name for MP4, and an item key for APE
+pub fn raw(tag: &mut dyn AudioTag, key: &str) -> Result<Option<String>, Unsupported> {
+    dispatch(
+        tag,
+        |t| {
+            t.get(key).and_then(|f| f.content().text()).map(String::from)
+                .or_else(|| t.extended_texts().find(|e| e.description == key).map(|e| e.value.clone()))
+        },
+        |t| {
+            let ident = mp4_ident(key);
+            let value = t.string(&ident).next().map(String::from);
+            value
+        },
+        |t| ape_text(t, key),
+    )
+}
+
+/// Removes every frame, atom, or item with the given key, named as for `raw`, without touching
+/// anything else. Returns how many were removed.
+pub fn strip(tag: &mut dyn AudioTag, key: &str) -> Result<usize, Unsupported> {
+    dispatch(
+        tag,
+        |t| {
+            let count = t.frames().filter(|f| f.id() == key).count();
+            t.remove(key);
+            count
+        },
+        |t| {
+            let ident = mp4_ident(key);
+            let count = t.data(&ident).count();
+            t.remove_data(&ident);
+            count
+        },
+        |t| if t.remove_item(key) { 1 } else { 0 },
+    )
+}
+
+/// Removes the frames that hold the sort order of a field, like `TSOP` for the artist. The fields
+/// are named as `--clear` takes them, and ones without a sort order remove nothing. Returns how
+/// many were removed.
+pub fn strip_sort(tag: &mut dyn AudioTag, field: &str) -> Result<usize, Unsupported> {
+    let (id3_key, mp4_key, ape_key) = match field {
+        "title" => ("TSOT", *b"sonm", "TitleSort"),
+        "artist" => ("TSOP", *b"soar", "ArtistSort"),
+        "album" => ("TSOA", *b"soal", "AlbumSort"),
+        "albumartist" => ("TSO2", *b"soaa", "AlbumArtistSort"),
+        _ => return Ok(0),
+    };
+
+    dispatch(
+        tag,
+        |t| {
+            let count = t.frames().filter(|f| f.id() == id3_key).count();
+            t.remove(id3_key);
+            count
+        },
+        |t| {
+            let ident = mp4ameta::FourCC(mp4_key);
+            let count = t.data(&ident).count();
+            t.remove_data(&ident);
+            count
+        },
+        // Taggers don't agree on the case of APE keys
+        |t| {
+            let keys: Vec<String> = t.iter().filter(|i| i.key.eq_ignore_ascii_case(ape_key)).map(|i| i.key.clone()).collect();
+            keys.iter().filter(|k| t.remove_item(k)).count()
+        },
+    )
+}
+
+/// Sets a text value that has no field of its own: a `TXXX` frame for ID3v2, an iTunes freeform
+/// atom for MP4, and an item for APE
+#[cfg(feature = "analysis")]
+pub fn set_user_text(tag: &mut dyn AudioTag, key: &str, value: &str) -> Result<(), Unsupported> {
+    dispatch(
+        tag,
+        |t| {
+            t.remove_extended_text(Some(key), None);
+            t.add_extended_text(key, value);
+        },
+        |t| {
+            let ident = mp4ameta::DataIdent::Freeform { mean: "com.apple.iTunes".to_string(), name: key.to_string() };
+            t.set_data(ident, mp4ameta::Data::Utf8(value.to_string()));
+        },
+        |t| set_ape_text(t, key, value),
+    )
+}
+
+/// The value of a frame, atom, or item
+pub enum RawValue {
+    Text(String),
+    Binary { mime_type: Option<String>, data: Vec<u8> },
+}
+
+fn id3_frame(frame: &id3::frame::Frame) -> (String, RawValue) {
+    use id3::frame::Content;
+
+    let id = frame.id();
+
+    match frame.content() {
+        Content::Text(s) | Content::Link(s) => (id.to_string(), RawValue::Text(s.clone())),
+        Content::ExtendedText(e) => (format!("{}:{}", id, e.description), RawValue::Text(e.value.clone())),
+        Content::ExtendedLink(l) => (format!("{}:{}", id, l.description), RawValue::Text(l.link.clone())),
+        Content::Comment(c) => (format!("{}:{}", id, c.description), RawValue::Text(c.text.clone())),
+        Content::Lyrics(l) => (format!("{}:{}", id, l.description), RawValue::Text(l.text.clone())),
+        Content::SynchronisedLyrics(l) => {
+            let lines: Vec<&str> = l.content.iter().map(|(_, s)| s.as_str()).collect();
+            (id.to_string(), RawValue::Text(lines.join("\n")))
+        }
+        Content::Picture(p) => (id.to_string(), RawValue::Binary { mime_type: Some(p.mime_type.clone()), data: p.data.clone() }),
+        Content::EncapsulatedObject(o) => (format!("{}:{}", id, o.description), RawValue::Binary { mime_type: Some(o.mime_type.clone()), data: o.data.clone() }),
+        Content::Unknown(b) => (id.to_string(), RawValue::Binary { mime_type: None, data: b.clone() }),
+    }
+}
+
+fn mp4_atom_key(ident: &mp4ameta::DataIdent) -> String {
+    match ident {
+        mp4ameta::DataIdent::FourCC(f) => f.iter().map(|b| char::from(*b)).collect(),
+        mp4ameta::DataIdent::Freeform { mean, name } => format!("----:{}:{}", mean, name),
+    }
+}
+
+fn mp4_atom(atom: &mp4ameta::AtomData) -> (String, RawValue) {
+    use mp4ameta::Data;
+
+    let key = mp4_atom_key(&atom.ident);
+
+    let value = match &atom.data {
+        Data::Utf8(s) | Data::Utf16(s) => RawValue::Text(s.clone()),
+        Data::Jpeg(d) => RawValue::Binary { mime_type: Some("image/jpeg".to_string()), data: d.clone() },
+        Data::Png(d) => RawValue::Binary { mime_type: Some("image/png".to_string()), data: d.clone() },
+        Data::Reserved(d) | Data::BeSigned(d) => RawValue::Binary { mime_type: None, data: d.clone() },
+    };
+
+    (key, value)
+}
+
+fn ape_item(item: &ape::Item) -> (String, RawValue) {
+    let value = match &item.value {
+        ape::ItemValue::Text(s) | ape::ItemValue::Locator(s) => RawValue::Text(s.clone()),
+        ape::ItemValue::Binary(d) => RawValue::Binary { mime_type: None, data: d.clone() },
+    };
+
+    (item.key.clone(), value)
+}
+
+/// Every frame, atom, or item in the tag, in the order they're stored. Keys that need more than
+/// their ID to tell them apart, like `TXXX` frames, have their description added after a `:`.
+pub fn all(tag: &mut dyn AudioTag) -> Result<Vec<(String, RawValue)>, Unsupported> {
+    dispatch(
+        tag,
+        |t| t.frames().map(id3_frame).collect(),
+        |t| t.atoms.iter().map(mp4_atom).collect(),
+        |t| t.iter().map(ape_item).collect(),
+    )
+}
+
+/// Merges every comment into one, joining their text with `separator`. The merged comment keeps
+/// the language of the first. Returns how many comments were merged, which is 0 when there was
+/// only one to begin with.
+pub fn merge_comments(tag: &mut dyn AudioTag, separator: &str) -> Result<usize, Unsupported> {
+    dispatch(
+        tag,
+        |t| {
+            let comments: Vec<id3::frame::Comment> = t.comments().cloned().collect();
+
+            if comments.len() < 2 {
+                return 0;
+            }
+
+            let texts: Vec<&str> = comments.iter().map(|c| c.text.as_str()).filter(|s| !s.is_empty()).collect();
+            let merged = id3::frame::Comment {
+                lang: comments[0].lang.clone(),
+                description: String::new(),
+                text: texts.join(separator),
+            };
+
+            t.remove("COMM");
+            t.add_comment(merged);
+            comments.len()
+        },
+        |t| {
+            let ident = mp4ameta::FourCC(*b"\xa9cmt");
+            let comments: Vec<String> = t.string(&ident).map(String::from).collect();
+
+            if comments.len() < 2 {
+                return 0;
+            }
+
+            let texts: Vec<&str> = comments.iter().map(String::as_str).filter(|s| !s.is_empty()).collect();
+            t.set_data(ident, mp4ameta::Data::Utf8(texts.join(separator)));
+            comments.len()
+        },
+        // APE tags only hold one item per key
+        |_| 0,
+    )
+}
+
+/// Keeps only the first `max` values of every text field that holds more than one. ID3v2 and APE
+/// separate values with a null, and MP4 has an atom for each. Returns each key that was cut down,
+/// along with how many values it lost.
+pub fn cap_values(tag: &mut dyn AudioTag, max: usize) -> Result<Vec<(String, usize)>, Unsupported> {
+    // Values end with a null in some taggers, which doesn't make for another value
+    fn split(s: &str) -> Vec<&str> {
+        s.strip_suffix('\0').unwrap_or(s).split('\0').collect()
+    }
+
+    dispatch(
+        tag,
+        |t| {
+            let mut capped = Vec::new();
+            let texts: Vec<(String, String)> = t.frames()
+                .filter_map(|f| Some((f.id().to_string(), f.content().text()?.to_string())))
+                .collect();
+
+            for (id, text) in texts {
+                let values = split(&text);
+
+                if values.len() > max {
+                    t.set_text(&id, values[..max].join("\0"));
+                    capped.push((id, values.len() - max));
+                }
+            }
+
+            capped
+        },
+        |t| {
+            let mut capped = Vec::new();
+            let mut counts: Vec<(&mp4ameta::DataIdent, usize)> = Vec::new();
+
+            for atom in &t.atoms {
+                if !matches!(atom.data, mp4ameta::Data::Utf8(_) | mp4ameta::Data::Utf16(_)) {
+                    continue;
+                }
+
+                match counts.iter_mut().find(|(ident, _)| *ident == &atom.ident) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((&atom.ident, 1)),
+                }
+            }
+
+            let over: Vec<(mp4ameta::DataIdent, usize)> = counts.into_iter()
+                .filter(|(_, count)| *count > max)
+                .map(|(ident, count)| (ident.clone(), count))
+                .collect();
+
+            for (ident, count) in over {
+                capped.push((mp4_atom_key(&ident), count - max));
+
+                let mut seen = 0;
+                t.atoms.retain(|a| {
+                    if a.ident != ident {
+                        return true;
+                    }
+                    seen += 1;
+                    seen <= max
+                });
+            }
+
+            capped
+        },
+        |t| {
+            let mut capped = Vec::new();
+            let texts: Vec<(String, String)> = t.iter()
+                .filter_map(|i| match &i.value {
+                    ape::ItemValue::Text(s) => Some((i.key.clone(), s.clone())),
+                    _ => None,
+                })
+                .collect();
+
+            for (key, text) in texts {
+                let values = split(&text);
+
+                if values.len() > max {
+                    set_ape_text(t, &key, &values[..max].join("\0"));
+                    capped.push((key, values.len() - max));
+                }
+            }
+
+            capped
+        },
+    )
+}
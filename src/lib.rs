@@ -1,15 +1,37 @@
 extern crate getopts;
+use std::cell::OnceCell;
 use std::cmp;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{self, Read, Write, Cursor};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write, Cursor, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "analysis")]
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use getopts::{Options, Fail, Matches};
 use image::{ImageFormat, io::Reader};
-use lofty::{self, Tag, AudioTag, Picture, MimeType};
+use lofty::{self, AudioTag, Picture, MimeType};
 
-#[derive(PartialEq, Eq, Hash, Debug)]
-enum Field {
+#[cfg(feature = "analysis")]
+mod analysis;
+mod containers;
+mod encoding;
+mod genres;
+mod inner;
+mod properties;
+mod repair;
+use properties::Properties;
+use repair::Repair;
+
+/// A field of a tag that can be printed, set, or cleared
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Field {
     Track,
     Year,
     Disc,
@@ -18,27 +40,87 @@ enum Field {
     Artist,
     Album,
     AlbumArtist,
+    Genre,
+    Composer,
+    Comment,
 
     Image,
 }
 
+/// The value a field is set to. Track, year, and disc take `Int` (or `IntOf` for track and disc),
+/// the image takes `File` or `Bytes`, and every other field takes `Str`. `StdIn` stands in for a
+/// value read from stdin when the commands are run.
 #[derive(Debug)]
-enum Data {
+pub enum Data {
     Str(String),
     Int(i32),
+    /// A number and the total it's out of, like a track given as `3/12`
+    IntOf(i32, u32),
     File(String),
     StdIn,
+    Bytes(Vec<u8>),
+}
+
+/// A piece of one level of a `--path-pattern`
+#[derive(Debug)]
+pub enum PathToken {
+    Text(String),
+    Field(Field),
 }
 
+/// A front cover taken from another file, along with the parts of it that `Picture` leaves out
 #[derive(Debug)]
-enum Command {
+pub struct Cover {
+    pub data: Vec<u8>,
+    pub mime_type: MimeType,
+    pub description: Option<String>,
+}
+
+/// A single thing to do to a tag, as given by the options
+#[derive(Debug)]
+pub enum Command {
     Print(Field),
     Clear(Field),
+    /// Clears every field, skipping the ones the tag format can't hold. Kept ahead of the other
+    /// commands so fields set alongside it keep their new values.
+    ClearAll,
     Set(Field, Data),
+    /// Sets the track number from the number the filename starts with, if it isn't already set
+    TrackFromFilename,
+    /// Fills in fields from the directories and name of the file, matched against a pattern with a
+    /// list of pieces for each level
+    ParsePath(Vec<Vec<PathToken>>),
+    /// Rewrites the genre using a map of lowercase genres to their canonical forms
+    MapGenre(HashMap<String, String>),
+    /// Marks a track as part of a compilation, with "Various Artists" as the album artist
+    SplitVariousArtists,
+    /// Sets the front cover to one copied from another file
+    CopyCover(Cover),
+    /// Sets the front cover from the first file with one of these names in the file's directory, if
+    /// it doesn't have a cover yet
+    AutoCover(Vec<String>),
+    /// Prints the value of a key that isn't one of the modeled fields
+    PrintRaw(String),
+    /// Removes every frame with a key that isn't one of the modeled fields
+    StripFrame(String),
+    /// Rewrites ID3v2 text frames that aren't stored as UTF-8
+    ReencodeUtf8,
+    /// Merges every comment into one, joined with a separator
+    MergeComments(String),
+    /// Keeps only the first values of fields that hold more than this many
+    CapValues(usize),
+    /// Rewrites the ways of writing "featuring" in the artist and title into one, optionally moving
+    /// featured artists out of the title and into the artist
+    NormalizeFeat { style: String, move_to_artist: bool },
+    /// Removes byte order marks, nulls, and other control characters from the text fields
+    StripControlChars,
+    /// Writes the cover to a file at this path
+    ExtractImage(String),
 }
 
 
 /// Returns on the failure of `Config::new` and `Config::exec`.
+#[derive(Debug)]
 pub struct Error {
     /// An error code expected to be returned when the program ends. An error 
     /// code of...
@@ -59,6 +141,16 @@ pub struct Error {
     /// `6` means that there were no "free parameters", aka filenames
     ///
     /// `7` means that there was an error when trying to edit the tags of a file
+    ///
+    /// `8` means that `--keep-going` was used and some, but not all, of the files failed. When
+    /// every file fails, the code of the first failure is used instead.
+    ///
+    /// `9` means that a file failed a check, like `--tag-size-limit`, `--require-image`,
+    /// `--guard-audio-checksum`, `--roundtrip-test`, `--print-bom-issues`, or `--genre-validate`
+    ///
+    /// `10` means that `--confirm` was used and the changes weren't confirmed
+    ///
+    /// `11` means that analyzing a file took longer than `--halt-timeout`
     pub error_code: i32,
     
     /// String expected to be printed right before the end of the program.
@@ -80,6 +172,78 @@ impl Error {
 pub struct Config {
     files: Vec<String>,
     commands: Vec<Command>,
+    properties: bool,
+    keep_going: bool,
+    validate_image: bool,
+    art_info: bool,
+    modified_only: bool,
+    print_path: bool,
+    no_default_print: bool,
+    /// Skips the printout after files are only edited, while still printing what was asked for
+    quiet: bool,
+    strict: bool,
+    export_image_dir: Option<String>,
+    dump_image: bool,
+    tag_size_limit: Option<usize>,
+    require_image: bool,
+    id3_version: Option<id3::Version>,
+    confirm: bool,
+    yes: bool,
+    export_cue: bool,
+    stats: bool,
+    repair: bool,
+    compact: bool,
+    json: bool,
+    transaction: bool,
+    /// How many files `--jobs` works on at once
+    jobs: usize,
+    dry_run: bool,
+    /// The objects printed by `--json`, kept until every file is done so they can be put in an array
+    /// Each is kept with the file it's for, since `--jobs` can finish files out of order
+    json_objects: Mutex<Vec<(String, String)>>,
+    all_tags_json: bool,
+    field_presence: bool,
+    print_encoding: bool,
+    print_hash: bool,
+    guard_audio: bool,
+    /// Skips reading a file back once it's written, and the printout after it
+    no_read_after_write: bool,
+    /// The directory `--print-relative` shows paths relative to
+    relative_to: Option<PathBuf>,
+    roundtrip: bool,
+    bom_issues: bool,
+    /// The genres `--genre-validate` allows, lowercased when `--genre-ignore-case` is given
+    allowed_genres: Option<Vec<String>>,
+    genre_ignore_case: bool,
+    changed_fields: bool,
+    /// The name to write each file's cover to in its directory, when `--write-cover-to-sidecar`
+    /// is given. An empty name picks `folder` with the image's extension.
+    sidecar: Option<String>,
+    force: bool,
+    tag_count: bool,
+    clear_related: bool,
+    summary_json: bool,
+    field_json: Option<Field>,
+    /// The fields `--print-duplicates-by-tag` groups files by
+    duplicates_by: Option<Vec<Field>>,
+    /// The field `--print-version-of` looks for in each tag container
+    version_of: Option<Field>,
+    csv: bool,
+    #[cfg(feature = "analysis")]
+    replaygain: bool,
+    #[cfg(feature = "analysis")]
+    halt_timeout: Option<Duration>,
+    /// The directory `--watch` tags new files in
+    watch: Option<String>,
+    /// Commands for single files, from `--fields-from`
+    file_commands: HashMap<String, Vec<Command>>,
+    only: Vec<Field>,
+    /// The order `printout` shows the fields in, from `--field-order`
+    field_order: Vec<Field>,
+    padding: Padding,
+    /// Where warnings and messages about what's being done go, stderr unless `warnings_to` says
+    /// otherwise
+    warnings: Mutex<Box<dyn Write + Send>>,
     opts: Options,
     name: String,
 }
@@ -94,6 +258,9 @@ fn str_to_field(s: &str) -> Option<Field> {
         "artist" => Some(Field::Artist),
         "album" => Some(Field::Album),
         "albumartist" => Some(Field::AlbumArtist),
+        "genre" => Some(Field::Genre),
+        "composer" => Some(Field::Composer),
+        "comment" => Some(Field::Comment),
 
         "image" => Some(Field::Image),
 
@@ -101,8 +268,7 @@ fn str_to_field(s: &str) -> Option<Field> {
     }
 }
 
-#[allow(dead_code)]
-fn field_to_str(f: &Field) -> &str {
+fn field_to_str(f: &Field) -> &'static str {
     match f {
         Field::Track => "track",
         Field::Year => "year",
@@ -112,415 +278,6322 @@ fn field_to_str(f: &Field) -> &str {
         Field::Artist => "artist",
         Field::Album => "album",
         Field::AlbumArtist => "albumartist",
+        Field::Genre => "genre",
+        Field::Composer => "composer",
+        Field::Comment => "comment",
 
         Field::Image => "image",
     }
 }
 
-fn printout(tag: &dyn AudioTag) -> String {
+/// Whether a field is shared by every track of an album, rather than being different for each track
+fn is_album_field(f: &Field) -> bool {
+    match f {
+        Field::Disc | Field::Year | Field::Album | Field::AlbumArtist | Field::Genre | Field::Comment | Field::Image => true,
+        Field::Track | Field::Title | Field::Artist | Field::Composer => false,
+    }
+}
+
+/// Whether a field can be set to this kind of data, as described on `Data`
+fn data_fits(f: &Field, d: &Data) -> bool {
+    match (f, d) {
+        (_, Data::StdIn) => true,
+        (Field::Track | Field::Disc, Data::Int(_) | Data::IntOf(..)) => true,
+        (Field::Year, Data::Int(_)) => true,
+        (Field::Image, Data::File(_) | Data::Bytes(_)) => true,
+        (Field::Track | Field::Disc | Field::Year | Field::Image, _) => false,
+        (_, Data::Str(_)) => true,
+        _ => false,
+    }
+}
+
+/// The file extension usually used for an image of this type
+fn mime_to_ext(mime_type: MimeType) -> &'static str {
+    match mime_type {
+        MimeType::Png => "png",
+        MimeType::Jpeg => "jpg",
+        MimeType::Tiff => "tiff",
+        MimeType::Bmp => "bmp",
+        MimeType::Gif => "gif",
+    }
+}
+
+/// Describes a cover for `--print-art-info`, like `image/jpeg, 600x600, 48213 bytes`. The
+/// dimensions are read from the image's header, and are `unknown dimensions` when it can't be read.
+fn art_info(picture: &Picture) -> String {
+    let mime: &str = picture.mime_type.into();
+
+    let dimensions = Reader::new(Cursor::new(picture.data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|r| r.into_dimensions().ok());
+
+    match dimensions {
+        Some((width, height)) => format!("{}, {}x{}, {} bytes", mime, width, height, picture.data.len()),
+        None => format!("{}, unknown dimensions, {} bytes", mime, picture.data.len()),
+    }
+}
+
+/// Formats a duration as `m:ss`
+fn format_length(duration: Option<Duration>) -> String {
+    match duration {
+        Some(d) => {
+            let secs = d.as_secs_f64().round() as u64;
+            format!("{}:{:02}", secs / 60, secs % 60)
+        }
+        None => "Unknown".to_string(),
+    }
+}
+
+/// The fields shown by `printout`, in the order they're shown
+const PRINTOUT_FIELDS: [Field; 11] = [
+    Field::Disc,
+    Field::Track,
+    Field::Title,
+    Field::Artist,
+    Field::Album,
+    Field::AlbumArtist,
+    Field::Genre,
+    Field::Composer,
+    Field::Comment,
+    Field::Image,
+    Field::Year,
+];
+
+/// How many digits numeric fields are zero-padded to when they're shown. Only affects display,
+/// never what gets written.
+#[derive(Default)]
+struct Padding {
+    track: usize,
+    disc: usize,
+}
+
+fn field_label(field: &Field) -> &'static str {
+    match field {
+        Field::Disc => "Disc",
+        Field::Track => "Track",
+        Field::Title => "Title",
+        Field::Artist => "Artist",
+        Field::Album => "Album",
+        Field::AlbumArtist => "Album Arist",
+        Field::Genre => "Genre",
+        Field::Composer => "Composer",
+        Field::Comment => "Comment",
+        Field::Image => "Image",
+        Field::Year => "Year",
+    }
+}
+
+fn field_value(tag: &mut dyn AudioTag, field: &Field, padding: &Padding) -> String {
+    match field {
+        Field::Disc => match tag.total_discs() {
+            Some(total) => format!("{:0width$}/{}", tag.disc_number().unwrap_or(0), total, width = padding.disc),
+            None => format!("{:0width$}", tag.disc_number().unwrap_or(0), width = padding.disc),
+        },
+        Field::Track => format!("{:0width$}", tag.track_number().unwrap_or(0), width = padding.track),
+        Field::Title => tag.title().unwrap_or("").to_string(),
+        Field::Artist => tag.artist_str().unwrap_or("").to_string(),
+        Field::Album => tag.album_title().unwrap_or("").to_string(),
+        Field::AlbumArtist => tag.album_artist_str().unwrap_or("").to_string(),
+        Field::Genre => inner::genre(tag).ok().flatten().unwrap_or_default(),
+        Field::Composer => inner::composer(tag).ok().flatten().unwrap_or_default(),
+        Field::Comment => inner::comment(tag).ok().flatten().unwrap_or_default(),
+        Field::Image => match tag.album_cover() { Some(_) => "Present", None => "No image" }.to_string(),
+        Field::Year => tag.year().unwrap_or(0).to_string(),
+    }
+}
+
+/// Whether the tag has a value for the field
+fn field_present(tag: &mut dyn AudioTag, field: &Field) -> bool {
+    match field {
+        Field::Disc => tag.disc_number().is_some(),
+        Field::Track => tag.track_number().is_some(),
+        Field::Title => tag.title().is_some(),
+        Field::Artist => tag.artist_str().is_some(),
+        Field::Album => tag.album_title().is_some(),
+        Field::AlbumArtist => tag.album_artist_str().is_some(),
+        Field::Genre => matches!(inner::genre(tag), Ok(Some(_))),
+        Field::Composer => matches!(inner::composer(tag), Ok(Some(_))),
+        Field::Comment => matches!(inner::comment(tag), Ok(Some(_))),
+        Field::Image => tag.album_cover().is_some(),
+        Field::Year => tag.year().is_some(),
+    }
+}
+
+fn printout_field(tag: &mut dyn AudioTag, field: &Field, padding: &Padding) -> String {
+    format!("{}: {}\n", field_label(field), field_value(tag, field, padding))
+}
+
+/// The printout fields of a tag as they were before any commands ran, so the changes can be
+/// shown afterwards
+struct Snapshot {
+    values: Vec<String>,
+    image: Option<Vec<u8>>,
+    /// The values as they're given by `--json`, for `--dry-run --json`
+    json: Vec<Option<String>>,
+}
+
+impl Snapshot {
+    fn new(tag: &mut dyn AudioTag, padding: &Padding) -> Snapshot {
+        Snapshot {
+            values: PRINTOUT_FIELDS.iter().map(|f| field_value(tag, f, padding)).collect(),
+            image: tag.album_cover().map(|p| p.data.to_vec()),
+            json: PRINTOUT_FIELDS.iter().map(|f| printout_json_value(tag, f)).collect(),
+        }
+    }
+
+    /// Whether `field` is different in `tag` from its value of `old` in the snapshot
+    fn is_changed(&self, tag: &mut dyn AudioTag, field: &Field, old: &str, padding: &Padding) -> bool {
+        match field {
+            Field::Image => self.image.as_deref() != tag.album_cover().map(|p| p.data),
+            _ => old != field_value(tag, field, padding),
+        }
+    }
+
+    /// The names of the fields that are different in `tag`
+    fn changed_fields(&self, tag: &mut dyn AudioTag, padding: &Padding) -> Vec<&'static str> {
+        PRINTOUT_FIELDS.iter().zip(&self.values)
+            .filter(|(f, old)| self.is_changed(tag, f, old, padding))
+            .map(|(f, _)| field_to_str(f))
+            .collect()
+    }
+
+    /// Describes each field that's different in `tag`, one per line
+    fn changes(&self, tag: &mut dyn AudioTag, padding: &Padding) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        for (f, old) in PRINTOUT_FIELDS.iter().zip(&self.values) {
+            if !self.is_changed(tag, f, old, padding) {
+                continue;
+            }
+
+            let new = field_value(tag, f, padding);
+            let new = if *f == Field::Image && *old == new { "Replaced".to_string() } else { new };
+            changes.push(format!("{}: {} -> {}", field_label(f), old, new));
+        }
+
+        changes
+    }
+
+    /// Each field that's different in `tag` as a JSON object with its name, and its old and new
+    /// values as `--json` gives them
+    fn json_changes(&self, tag: &mut dyn AudioTag, padding: &Padding) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        for ((f, old), old_json) in PRINTOUT_FIELDS.iter().zip(&self.values).zip(&self.json) {
+            if !self.is_changed(tag, f, old, padding) {
+                continue;
+            }
+
+            let new_json = printout_json_value(tag, f);
+            changes.push(format!(
+                "{{\"field\":{},\"old\":{},\"new\":{}}}",
+                json_string(field_to_str(f)), old_json.as_deref().unwrap_or("null"), new_json.as_deref().unwrap_or("null"),
+            ));
+        }
+
+        changes
+    }
+}
+
+/// Formats the tag for reading, with the fields in `order` followed by the length. If `only` has
+/// any fields, just those fields are shown, in the order given.
+fn printout(tag: &mut dyn AudioTag, properties: &Properties, only: &[Field], order: &[Field], padding: &Padding) -> String {
     let mut result = String::new();
-    result.push_str(&format!("Disc: {}\n", tag.disc_number().unwrap_or(0)));
-    result.push_str(&format!("Track: {}\n", tag.track_number().unwrap_or(0)));
-    result.push_str(&format!("Title: {}\n", tag.title().unwrap_or("")));
-    result.push_str(&format!("Artist: {}\n", tag.artist_str().unwrap_or("")));
-    result.push_str(&format!("Album: {}\n", tag.album_title().unwrap_or("")));
-    result.push_str(&format!("Album Arist: {}\n", tag.album_artist_str().unwrap_or("")));
-    result.push_str(&format!("Image: {}\n", match tag.album_cover() { Some(_) => "Present", None => "No image" }));
-    result.push_str(&format!("Year: {}\n", tag.year().unwrap_or(0)));
+
+    if only.is_empty() {
+        for f in order {
+            result.push_str(&printout_field(tag, f, padding));
+        }
+        result.push_str(&format!("Length: {}\n", format_length(properties.duration)));
+    } else {
+        for f in only {
+            result.push_str(&printout_field(tag, f, padding));
+        }
+    }
 
     result
 }
 
-impl Config {
-    /// Parses arguments and creates a Config struct
-    pub fn new(args: &[String], name: &str) -> Result<Config, Error> { 
-       let mut opts = Options::new();
+fn fail_to_str(f: Fail) -> String {
+    match f {
+        Fail::ArgumentMissing(o) => format!("Argument for option '{}' missing", o),
+        Fail::UnrecognizedOption(o) => format!("Unknown option '{}'", o),
+        Fail::OptionMissing(o) => format!("Option '{}' missing", o),
+        Fail::OptionDuplicated(o) => format!("Option '{}' used more than once", o),
+        Fail::UnexpectedArgument(o) => format!("Unexpected argument for '{}'", o)
+    }
+}
 
-       // Flags
-       opts.optflag("h", "help", "Print this help text");
+fn command_field(c: &Command) -> Option<&Field> {
+    match c {
+        Command::Print(f) => Some(f),
+        Command::Clear(f) => Some(f),
+        Command::Set(f, _) => Some(f),
+        Command::TrackFromFilename => Some(&Field::Track),
+        Command::ParsePath(_) | Command::ClearAll => None,
+        Command::MapGenre(_) | Command::PrintRaw(_) | Command::StripFrame(_) | Command::ReencodeUtf8 | Command::MergeComments(_) | Command::CapValues(_) | Command::NormalizeFeat { .. } | Command::StripControlChars => None,
+        Command::SplitVariousArtists => Some(&Field::AlbumArtist),
+        Command::CopyCover(_) | Command::AutoCover(_) | Command::ExtractImage(_) => Some(&Field::Image),
+    }
+}
 
-       // Options
-       opts.optmulti("", "clear", "Clear out a field", "FIELD");
+/// Whether two commands act on the same thing, so only one of them should be kept
+fn overlaps(a: &Command, b: &Command) -> bool {
+    match (a, b) {
+        (Command::PrintRaw(x), Command::PrintRaw(y)) | (Command::StripFrame(x), Command::StripFrame(y)) => return x == y,
+        _ => {}
+    }
 
-       // Field Options
-       opts.optflagopt("", "track", "The track number", "NUM");
-       opts.optflagopt("", "year", "The year the track released", "NUM");
-       opts.optflagopt("", "disc", "The disc this track is on", "NUM");
+    match (command_field(a), command_field(b)) {
+        (Some(x), Some(y)) => x == y,
+        (None, None) => mem::discriminant(a) == mem::discriminant(b),
+        _ => false,
+    }
+}
 
-       opts.optflagopt("", "title", "The song name", "STRING");
-       opts.optflagopt("", "artist", "The song's artist", "STRING");
-       opts.optflagopt("", "album", "The song's album", "STRING");
-       opts.optflagopt("", "albumartist", "The album artist", "STRING");
-       opts.optflagopt("", "comment", "A description/comment about the song", "STRING");
+/// Describes what a command changes, for `--confirm`. Commands that only print give `None`.
+fn describe_change(c: &Command) -> Option<String> {
+    match c {
+        Command::Set(f, _) => Some(format!("set {}", field_to_str(f))),
+        Command::Clear(f) => Some(format!("clear {}", field_to_str(f))),
+        Command::ClearAll => Some("clear every field".to_string()),
+        Command::TrackFromFilename => Some("set track from the filename".to_string()),
+        Command::ParsePath(_) => Some("fill in fields from the directory structure".to_string()),
+        Command::MapGenre(_) => Some("rewrite genres using the genre map".to_string()),
+        Command::SplitVariousArtists => Some("mark as a compilation by Various Artists".to_string()),
+        Command::CopyCover(_) => Some("copy the cover".to_string()),
+        Command::AutoCover(_) => Some("add covers from the files' folders".to_string()),
+        Command::StripFrame(key) => Some(format!("strip `{}` frames", key)),
+        Command::ReencodeUtf8 => Some("re-encode text frames as UTF-8".to_string()),
+        Command::MergeComments(_) => Some("merge comments".to_string()),
+        Command::CapValues(max) => Some(format!("keep at most {} values per field", max)),
+        Command::NormalizeFeat { style, .. } => Some(format!("write featured artists with \"{}\"", style)),
+        Command::StripControlChars => Some("strip stray control characters from text fields".to_string()),
+        Command::Print(_) | Command::PrintRaw(_) | Command::ExtractImage(_) => None,
+    }
+}
 
-       opts.optflagopt("", "image", "The album artwork/photo that goes along with the song. `-` for stdin, `./-` for a file literally named `-`.", "FILE");
+/// Quotes a string for a CUE sheet. CUE sheets have no way to escape a quote, so double quotes are
+/// swapped for single ones.
+fn cue_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
 
-       let matches: Matches;
+/// The extensions of the audio files lofty can read
+const AUDIO_EXTENSIONS: [&str; 13] = ["mp3", "flac", "ogg", "oga", "opus", "m4a", "m4b", "m4p", "mp4", "wav", "aif", "aiff", "ape"];
 
-       match opts.parse(args) {
-            Ok(m) => matches = m,
-            Err(f) => {
-                let err_str = match f {
-                    Fail::ArgumentMissing(o) => format!("Argument for option '{}' missing", o),
-                    Fail::UnrecognizedOption(o) => format!("Unknown option '{}'", o),
-                    Fail::OptionMissing(o) => format!("Option '{}' missing", o),
-                    Fail::OptionDuplicated(o) => format!("Option '{}' used more than once", o),
-                    Fail::UnexpectedArgument(o) => format!("Unexpected argument for '{}'", o)
-                };
-                return Err(Error::new(name, &opts, Some(&err_str), 1));
-            }
-       }
+/// Whether a path has the extension of an audio file
+fn is_audio(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    AUDIO_EXTENSIONS.contains(&ext.as_str())
+}
 
-       // Make sure some files are specified
-       if matches.free.len() == 0 {
-           let error_str = "There were no files specified.";
-           return Err(Error::new(name, &opts, Some(error_str), 6));
-       }
+/// Every audio file under `dir` and its subdirectories, sorted by path, for `--recursive`.
+/// Directories that can't be read are skipped. Symlinks to files are always included, but symlinks
+/// to directories are only followed with `follow_symlinks`. Each directory is walked once, however
+/// many links lead to it, so a link back up the tree doesn't loop forever.
+fn audio_files_in(dir: &Path, follow_symlinks: bool) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    let mut walked: HashSet<PathBuf> = HashSet::new();
 
-       // Verify each file does exist
-       for f in &matches.free {
-           if !(Path::new(&f).is_file()) {
-               let err_str = format!("File {} does not exist, is a broken symlink, or we may not have valid permissions", &f);
-               return Err(Error::new(name, &opts, Some(&err_str), 2));
-           }
-       }
+    while let Some(dir) = dirs.pop() {
+        if !fs::canonicalize(&dir).map(|real| walked.insert(real)).unwrap_or(false) {
+            continue;
+        }
 
-       // Flags
-       if matches.opt_present("help") {
-            return Err(Error::new(name, &opts, None, 0));
-       }
-       
-       // Fields
-       let mut commands: Vec<Command> = Vec::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
 
-       // Integer Fields
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
 
-       if matches.opt_present("track") {
-           if let Some(s) = matches.opt_str("track") {
-                let val;
-                match i32::from_str_radix(s.trim(),10) {
-                    Ok(i) => val = i,
-                    Err(_) => { 
-                        let err_str = "'track', 'year', and 'disc' feeds need to be integers. (Error on 'track' field)";
-                        return Err(Error::new(name, &opts, Some(err_str), 3));
-                    }
-                }   
-                commands.push(Command::Set(Field::Track, Data::Int(val)));
-           } else {
-               commands.push(Command::Print(Field::Track));
-           }
-       }
+            match entry.file_type() {
+                Ok(t) if t.is_dir() || (follow_symlinks && t.is_symlink() && path.is_dir()) => dirs.push(path),
+                _ if path.is_file() && is_audio(&path) => files.push(path.to_string_lossy().into_owned()),
+                _ => {}
+            }
+        }
+    }
 
-       if matches.opt_present("year") {
-           if let Some(s) = matches.opt_str("year") {
-                let val;
-                match i32::from_str_radix(s.trim(),10) {
-                    Ok(i) => val = i,
-                    Err(_) => { 
-                        let err_str = "'track', 'year', and 'disc' feeds need to be integers. (Error on 'year' field)";
-                        return Err(Error::new(name, &opts, Some(err_str), 3));
-                    }
-                }   
-                commands.push(Command::Set(Field::Year, Data::Int(val)));
-           } else {
-               commands.push(Command::Print(Field::Year));
-           }
-       }
+    files.sort();
+    files
+}
 
-       if matches.opt_present("disc") {
-           if let Some(s) = matches.opt_str("disc") {
-                let val;
-                match i32::from_str_radix(s.trim(),10) {
-                    Ok(i) => val = i,
-                    Err(_) => { 
-                        let err_str = "'track', 'year', and 'disc' feeds need to be integers. (Error on 'disc' field)";
-                        return Err(Error::new(name, &opts, Some(err_str), 3));
-                    }
-                }   
-                commands.push(Command::Set(Field::Disc, Data::Int(val)));
-           } else {
-               commands.push(Command::Print(Field::Disc));
-           }
-       }
+/// `path` written relative to `base`, with `..` for each level of `base` it isn't under. Both
+/// need to be absolute, or both relative to the same directory.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
 
-       // String Fields
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
 
-       if matches.opt_present("title") {
-           if let Some(s) = matches.opt_str("title") {
-               commands.push(Command::Set(Field::Title, Data::Str(s)));
-           } else {
-               commands.push(Command::Print(Field::Title));
-           }
-       }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
 
-       if matches.opt_present("artist") {
-           if let Some(s) = matches.opt_str("artist") {
-               commands.push(Command::Set(Field::Artist, Data::Str(s)));
-           } else {
-               commands.push(Command::Print(Field::Artist));
-           }
+    relative
+}
 
-       }
+/// The CUE sheet file type for a music file, going by its extension
+fn cue_file_type(path: &str) -> &'static str {
+    let ext = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
 
-       if matches.opt_present("album") {
-           if let Some(s) = matches.opt_str("album") {
-               commands.push(Command::Set(Field::Album, Data::Str(s)));
-           } else {
-               commands.push(Command::Print(Field::Album));
-           }
+    match ext.as_str() {
+        "mp3" => "MP3",
+        "aif" | "aiff" => "AIFF",
+        _ => "WAVE",
+    }
+}
 
-       }
+/// Formats the tag on a single line, for `--compact`. If `only` has any fields, just those fields
+/// are shown, in the order given. Empty fields are left out.
+fn printout_compact(f: &str, tag: &mut dyn AudioTag, only: &[Field], padding: &Padding) -> String {
+    let mut value = |field: &Field| match field {
+        Field::Year if tag.year().is_none() => String::new(),
+        _ => field_value(tag, field, padding),
+    };
 
-       if matches.opt_present("albumartist") {
-           if let Some(s) = matches.opt_str("albumartist") {
-               commands.push(Command::Set(Field::AlbumArtist, Data::Str(s)));
-           } else {
-               commands.push(Command::Print(Field::AlbumArtist));
-           }
+    let mut result = format!("{}:", f);
 
-       }
+    if !only.is_empty() {
+        let values: Vec<String> = only.iter().map(&mut value).filter(|v| !v.is_empty()).collect();
+        if !values.is_empty() {
+            result.push_str(&format!(" {}", values.join(" - ")));
+        }
 
-       // File Fields
-       
-       if matches.opt_present("image") {
-           if let Some(s) = matches.opt_str("image") {
-                if s != "-" { // If we shouldn't read from stdin
-                    if !(Path::new(&s).is_file()) {
-                        let err_str = format!("File {} does not exist, is a broken symlink, or we may not have valid permissions", &s);
-                        return Err(Error::new(name, &opts, Some(&err_str), 2));
-                    } else {
-                        commands.push(Command::Set(Field::Image, Data::File(s)));
-                    }
-                } else {
-                    commands.push(Command::Set(Field::Image, Data::StdIn));
-                }
-           } else {
-               commands.push(Command::Print(Field::Image));
-           }
+        return result;
+    }
 
-       }
+    let names: Vec<String> = [Field::Artist, Field::Title].iter().map(&mut value).filter(|v| !v.is_empty()).collect();
+    let album: Vec<String> = [Field::Album, Field::Year].iter().map(value).filter(|v| !v.is_empty()).collect();
 
-       // Clear option
+    if !names.is_empty() {
+        result.push_str(&format!(" {}", names.join(" - ")));
+    }
 
-       let mut used: HashSet<&Field> = HashSet::new();
-       let mut clear_commands: Vec<Command> = Vec::new();
+    if !album.is_empty() {
+        result.push_str(&format!(" [{}]", album.join(", ")));
+    }
 
-       for c in &commands { // Find all of the fields for the set commands
-           match c {
-               Command::Set(f, _) => { used.insert(&f); }
-               Command::Print(f) => { used.insert(&f); }
-               Command::Clear(_) => { /* no-op */ },
-           }
-       }
+    result
+}
 
-       for s in matches.opt_strs("clear") { // For every clear command...
-           if let Some(f) = str_to_field(&s) {
-               if !(used.contains(&f)) { // If the field isn't in used in a set command
-                   clear_commands.push(Command::Clear(f)); // Then add a clear command
-               } else { // If the field is in the set command, error.
-                   let err_str = format!("Cannot clear and set/print field '{}' at the same time", &s);
-                   return Err(Error::new(name, &opts, Some(&err_str), 5));
-               }
-           } else { // If the clear command didn't contain a valid field, error.
-               let err_str = format!("Cannot clear '{}' field because it does not exist!", &s);
-               return Err(Error::new(name, &opts, Some(&err_str), 4));
-           }
-       }
+/// Quotes a CSV field if it needs it
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
 
-       for c in clear_commands {
-           commands.push(c);
-       }
+/// Quotes and escapes a string for JSON
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
 
-       Ok(Config {
-           files: matches.free,
-           commands: commands,
-           opts: opts,
-           name: name.to_string(),
-       })
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
     }
 
-    /// The main part of the program that does the metadata modifications
-    pub fn exec(self) -> Result<(), Error> {
-        for f in &self.files {
-            let mut tag = match Tag::new().read_from_path_signature(f) {
-                Ok(t) => t,
-                Err(_) => { 
-                    let err_str = format!("Failure to open `{}` for editing", f);
-                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
-                }
-            };
-            
-            if self.commands.is_empty() {
-                println!("{}", printout(&(*tag)));
+    result.push('"');
+    result
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |acc, (i, b)| acc | u32::from(*b) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize]));
             } else {
-                let mut need_to_write = false;
-                let mut did_print = false;
-    
-                for c in &self.commands {
-                    match c {
-                        Command::Set(f, d) => {
-                            need_to_write = true;
-
-                            match f {
-                                // Int Fields
-                                Field::Disc => {
-                                    if let Data::Int(i) = d {
-                                        tag.set_disc_number(cmp::max(*i, 0) as u32);
-                                    }
-                                    else { panic!("d isn't a int (disc)"); }
-                                }
-                                Field::Track => {
-                                    if let Data::Int(i) = d {
-                                        tag.set_track_number(cmp::max(*i, 0) as u32);
-                                    }
-                                    else { panic!("d isn't a int (track)"); }
-                                }
-                                Field::Year => {
-                                    if let Data::Int(i) = d {
-                                        tag.set_year(*i);
-                                    }
-                                    else { panic!("d isn't a int (year)"); }
-                                }
+                result.push('=');
+            }
+        }
+    }
 
-                                // Title Fields
-                                Field::Title => {
-                                    if let Data::Str(s) = d {
-                                        tag.set_title(s);
-                                    }
-                                    else { panic!("d isn't a string (title)"); }
-                                }
-                                Field::Artist => {
-                                    if let Data::Str(s) = d {
-                                        tag.set_artist(s);
-                                    }
-                                    else { panic!("d isn't a string (artist)"); }
-                                }
-                                Field::Album => {
-                                    if let Data::Str(s) = d {
-                                        tag.set_album_title(s);
-                                    }
-                                    else { panic!("d isn't a string (album)"); }
-                                }
-                                Field::AlbumArtist => {
-                                    if let Data::Str(s) = d {
-                                        tag.set_album_artist(s);
-                                    }
-                                    else { panic!("d isn't a string (albumartist)"); }
-                                }
+    result
+}
 
-                                // File Fields
-                                Field::Image => {
-                                    let mut buf: Vec<u8> = Vec::new();
-                                    
-                                    if let Data::File(s) = d {
-                                        let mut f = match File::open(s) {
-                                            Ok(f) => f,
-                                            Err(_) => {
-                                                let error_str = "Issue when opening image file.";
-                                                return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
-                                            }
-                                        };
-
-                                        if let Err(_) = f.read_to_end(&mut buf) {
-                                            let error_str = "Issue when reading image file.";
-                                            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
-                                        }
-                                    }
-
-                                    else if let Data::StdIn = d {
-                                        let mut stdin = io::stdin();
-
-                                        if let Err(_) = stdin.read_to_end(&mut buf) {
-                                            let error_str = "Issue when reading stdin.";
-                                            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
-                                        }
-                                    }
-
-                                    else { panic!("d isn't a file or stdin (image)"); }
-
-                                    let reader = Reader::new(Cursor::new(&buf))
-                                        .with_guessed_format().expect("'cursor io never fails'");
-                                    let mimetype = match reader.format() {
-                                        Some(ImageFormat::Png) => MimeType::Png,
-                                        Some(ImageFormat::Jpeg) => MimeType::Jpeg,
-                                        Some(ImageFormat::Tiff) => MimeType::Tiff,
-                                        Some(ImageFormat::Bmp) => MimeType::Bmp,
-                                        Some(ImageFormat::Gif) => MimeType::Gif,
-                                        _ => {
-                                            let error_str = "Unsupported image format (Supported: Png, Jpeg, Tiff, Bmp, Gif)";
-                                            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
-                                        }
-                                    };
-
-                                    let picture = Picture::new(&buf, mimetype);
-                                    tag.set_album_cover(picture);
-                                }
-                            }
-                        }
-                        Command::Clear(f) => {
-                            need_to_write = true;
-
-                            match f {
-                                // Int Fields
-                                Field::Disc => tag.remove_disc_number(),
-                                Field::Track => tag.remove_track_number(),
-                                Field::Year => tag.remove_year(),
-
-                                // Str Fields
-                                Field::Title => tag.remove_title(),
-                                Field::Artist => tag.remove_artist(),
-                                Field::Album => tag.remove_album_title(),
-                                Field::AlbumArtist => tag.remove_album_artists(),
-
-                                // File Fields
-                                Field::Image => tag.remove_album_cover(),
-                            }
-                        }
-                        Command::Print(f) => {
-                            did_print = true;
-
-                            match f {
-                                // Int Fields
-                                Field::Disc => println!("{}", tag.disc_number().unwrap_or(0)),
-                                Field::Track => println!("{}", tag.track_number().unwrap_or(0)),
-                                Field::Year => println!("{}", tag.year().unwrap_or(0)),
-
-                                // Str Fields
-                                Field::Title => println!("{}", tag.title().unwrap_or("")),
-                                Field::Artist => println!("{}", tag.artist_str().unwrap_or("")),
-                                Field::Album => println!("{}", tag.album_title().unwrap_or("")),
-                                Field::AlbumArtist => println!("{}", tag.album_artist_str().unwrap_or("")),
-
-                                // File Fields
-                                Field::Image => {
-                                    if let Some(p) = tag.album_cover() {
-                                        if let Err(_) = io::stdout().write_all(p.data) {
-                                            // This error message probably won't even make it to
-                                            // the user, lol.
-                                            let error_str = "Error when trying to print image to stdout";
-                                            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
-                                        }
-                                        println!(); // Write a newline separator
-                                    } else { println!(); }
-                                }
-                            }
-                        }
-                    }
-                }
-    
-                if need_to_write {
-                    if let Err(_) = tag.write_to_path(f) {
-                        let error_str = format!("Failed to write new tags to {}", f);
-                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
-                    }
-                }
+/// The 64 bit FNV-1a hash of some data. Unlike `DefaultHasher`, it's the same on every build.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3))
+}
 
-                if !did_print {
-                    println!("{}", printout(&(*tag)));
-                }
+/// Hashes a tag for `--print-hash`. Every frame is hashed when the tag format lets them be listed,
+/// and just the printout fields otherwise.
+fn tag_hash(tag: &mut dyn AudioTag) -> u64 {
+    let serialized = match inner::all(tag) {
+        Ok(frames) => all_tags_json("", &frames),
+        Err(_) => {
+            let mut fields = printout(tag, &Properties::default(), &[], &PRINTOUT_FIELDS, &Padding::default());
+            if let Some(p) = tag.album_cover() {
+                fields.push_str(&base64(p.data));
             }
+            fields
         }
+    };
 
-        Ok(())
+    fnv1a(serialized.as_bytes())
+}
+
+/// Hashes the audio of a file, leaving out its tags, for `--guard-audio-checksum`. `None` when the
+/// audio can't be found.
+fn audio_checksum(data: &[u8]) -> Option<u64> {
+    properties::audio_slices(data).map(|slices| fnv1a(&slices.concat()))
+}
+
+/// A field of a tag as a JSON value, with numbers kept as numbers and images base64 encoded. `None`
+/// when the field isn't set.
+fn json_value(tag: &mut dyn AudioTag, field: &Field) -> Option<String> {
+    match field {
+        Field::Disc => tag.disc_number().map(|n| n.to_string()),
+        Field::Track => tag.track_number().map(|n| n.to_string()),
+        Field::Year => tag.year().map(|n| n.to_string()),
+        Field::Title => tag.title().map(json_string),
+        Field::Artist => tag.artist_str().map(json_string),
+        Field::Album => tag.album_title().map(json_string),
+        Field::AlbumArtist => tag.album_artist_str().map(json_string),
+        Field::Genre => inner::genre(tag).ok().flatten().as_deref().map(json_string),
+        Field::Composer => inner::composer(tag).ok().flatten().as_deref().map(json_string),
+        Field::Comment => inner::comment(tag).ok().flatten().as_deref().map(json_string),
+        Field::Image => tag.album_cover().map(|p| json_string(&base64(p.data))),
+    }
+}
+
+/// Formats a single field of a tag as a line of JSON, for `--print-field-json`. A field that isn't
+/// set is `null`.
+fn field_json(f: &str, tag: &mut dyn AudioTag, field: &Field) -> String {
+    let value = json_value(tag, field);
+    format!("{{\"path\":{},\"field\":{},\"value\":{}}}", json_string(f), json_string(field_to_str(field)), value.unwrap_or_else(|| "null".to_string()))
+}
+
+/// A field as it's given in `printout_json`, which is `json_value` with the image's mime type in
+/// place of its data
+fn printout_json_value(tag: &mut dyn AudioTag, field: &Field) -> Option<String> {
+    match field {
+        Field::Image => tag.album_cover().map(|p| json_string(p.mime_type.into())),
+        _ => json_value(tag, field),
+    }
+}
+
+/// The changes `--dry-run` would make to a file as a JSON object, for `--dry-run --json`.
+/// `would_write` is whether the file would be written at all, since it can be without any of the
+/// printout fields changing.
+fn preview_json(f: &str, would_write: bool, changes: &[String]) -> String {
+    format!("{{\"path\":{},\"would_write\":{},\"changes\":[{}]}}", json_string(f), would_write, changes.join(","))
+}
+
+/// Formats the tag as a JSON object, for `--json`. Like `printout`, the length is included after
+/// the fields, along with the rest of the audio properties: the length in seconds, the bitrate in
+/// kbps, the sample rate in Hz, and the channels. If `only` has any fields, just those fields are
+/// included. The image is given as its mime type rather than its data, and a field or property
+/// that isn't known is `null`.
+fn printout_json(f: &str, tag: &mut dyn AudioTag, properties: &Properties, only: &[Field]) -> String {
+    let fields = if only.is_empty() { &PRINTOUT_FIELDS[..] } else { only };
+
+    let mut values: Vec<String> = fields.iter().map(|field| {
+        format!("{}:{}", json_string(field_to_str(field)), printout_json_value(tag, field).unwrap_or_else(|| "null".to_string()))
+    }).collect();
+
+    if only.is_empty() {
+        let or_null = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+
+        values.push(format!("\"length\":{}", or_null(properties.duration.map(|d| format!("{:.3}", d.as_secs_f64())))));
+        values.push(format!("\"bitrate\":{}", or_null(properties.bitrate.map(|n| n.to_string()))));
+        values.push(format!("\"sample_rate\":{}", or_null(properties.sample_rate.map(|n| n.to_string()))));
+        values.push(format!("\"channels\":{}", or_null(properties.channels.map(|n| n.to_string()))));
+    }
+
+    format!("{{\"path\":{},{}}}", json_string(f), values.join(","))
+}
+
+/// The most files a bare `--jobs` works on at once. Past this, the threads mostly wait on the disk.
+const MAX_DEFAULT_JOBS: usize = 8;
+
+/// How many files a bare `--jobs` works on at once: one for each CPU, up to `MAX_DEFAULT_JOBS`
+fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_DEFAULT_JOBS)
+}
+
+/// Formats the results of a run as a single line of JSON, for `--summary-json`. Files that were
+/// processed without being modified or failing count as unchanged, and skipped files are the ones
+/// never reached because an earlier file failed.
+fn summary_json(processed: usize, modified: usize, skipped: usize, failures: &[(&str, &Error)]) -> String {
+    let errors: Vec<String> = failures.iter().map(|(f, e)| {
+        format!("{{\"path\":{},\"message\":{},\"code\":{}}}", json_string(f), json_string(&e.error_str), e.error_code)
+    }).collect();
+
+    format!(
+        "{{\"processed\":{},\"modified\":{},\"unchanged\":{},\"skipped\":{},\"errored\":{},\"errors\":[{}]}}",
+        processed, modified, processed - modified - failures.len(), skipped, failures.len(), errors.join(","),
+    )
+}
+
+/// Counts the frames of a tag for `--print-tag-count`, in the order their keys first show up
+fn printout_tag_count(keys: &[String]) -> String {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+
+    for key in keys {
+        match counts.iter_mut().find(|(k, _)| k == key) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((key, 1)),
+        }
+    }
+
+    let mut result = format!("Frames: {}\n", keys.len());
+
+    for (key, n) in counts {
+        let mark = if n > 1 { " (duplicated)" } else { "" };
+        result.push_str(&format!("  {}: {}{}\n", key, n, mark));
+    }
+
+    result
+}
+
+/// Formats every frame of a tag as a single line of JSON, for `--all-tags-json`
+fn all_tags_json(f: &str, frames: &[(String, inner::RawValue)]) -> String {
+    let frames: Vec<String> = frames.iter().map(|(key, value)| match value {
+        inner::RawValue::Text(s) => format!("{{\"key\":{},\"value\":{}}}", json_string(key), json_string(s)),
+        inner::RawValue::Binary { mime_type, data } => {
+            let mime_type = match mime_type {
+                Some(m) => format!(",\"mime_type\":{}", json_string(m)),
+                None => String::new(),
+            };
+            format!("{{\"key\":{}{},\"data\":\"{}\"}}", json_string(key), mime_type, base64(data))
+        }
+    }).collect();
+
+    format!("{{\"path\":{},\"tags\":[{}]}}", json_string(f), frames.join(","))
+}
+
+/// Reads a genre map made up of `from = to` lines. Blank lines and lines starting with `#` are
+/// ignored. On failure, the number of the line that couldn't be parsed is returned.
+fn parse_genre_map(contents: &str) -> Result<HashMap<String, String>, usize> {
+    let mut map = HashMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+                map.insert(from.trim().to_lowercase(), to.trim().to_string());
+            }
+            _ => return Err(i + 1),
+        }
+    }
+
+    Ok(map)
+}
+
+/// Reads a `--fields-from` manifest, where each line is a filename followed by tab separated
+/// `key=value` pairs. An empty value clears the field. Blank lines and lines starting with `#` are
+/// ignored. Errors name the line that couldn't be parsed.
+fn read_fields_file(manifest: &str, years: Option<(i32, i32)>, name: &str, opts: &Options) -> Result<Vec<(String, Vec<Command>)>, Error> {
+    let contents = match fs::read_to_string(manifest) {
+        Ok(c) => c,
+        Err(_) => {
+            let err_str = format!("Failed to read fields file {}", manifest);
+            return Err(Error::new(name, opts, Some(&err_str), 2));
+        }
+    };
+
+    let line_error = |line: usize, err: &str, code: i32| {
+        let err_str = format!("Line {} of fields file {}: {}", line, manifest, err);
+        Err(Error::new(name, opts, Some(&err_str), code))
+    };
+
+    let mut files: Vec<(String, Vec<Command>)> = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut cells = line.split('\t');
+        let file = cells.next().unwrap_or("").trim().to_string();
+
+        if file.is_empty() {
+            return line_error(i + 1, "the filename is missing", 1);
+        }
+
+        if !Path::new(&file).is_file() {
+            let err = format!("file {} does not exist, is a broken symlink, or we may not have valid permissions", file);
+            return line_error(i + 1, &err, 2);
+        }
+
+        if files.iter().any(|(f, _)| *f == file) {
+            return line_error(i + 1, &format!("{} was already given on an earlier line", file), 1);
+        }
+
+        let mut commands: Vec<Command> = Vec::new();
+
+        // Spreadsheets leave empty cells behind for the columns a row doesn't use
+        for cell in cells.filter(|c| !c.trim().is_empty()) {
+            let (key, value) = match cell.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => return line_error(i + 1, &format!("'{}' isn't a `key=value` pair", cell.trim()), 1),
+            };
+
+            let field = match str_to_field(key) {
+                Some(f) => f,
+                None => return line_error(i + 1, &format!("'{}' isn't a field", key), 4),
+            };
+
+            if commands.iter().any(|c| command_field(c) == Some(&field)) {
+                return line_error(i + 1, &format!("'{}' is given more than once", key), 1);
+            }
+
+            if value.is_empty() {
+                commands.push(Command::Clear(field));
+                continue;
+            }
+
+            let data = match field {
+                Field::Track | Field::Disc => match parse_number_of(value, key, name, opts) {
+                    Ok(d) => d,
+                    Err(e) => return line_error(i + 1, &e.error_str, e.error_code),
+                },
+                Field::Year => match value.parse::<i32>() {
+                    Ok(n) => {
+                        if let Some((min, max)) = years {
+                            if n < min || n > max {
+                                let err = format!("the year {} is outside of the allowed range {}:{}", n, min, max);
+                                return line_error(i + 1, &err, 3);
+                            }
+                        }
+
+                        Data::Int(n)
+                    }
+                    Err(_) => return line_error(i + 1, &format!("'{}' needs to be an integer", key), 3),
+                },
+                Field::Image => {
+                    if !Path::new(value).is_file() {
+                        let err = format!("image {} does not exist, is a broken symlink, or we may not have valid permissions", value);
+                        return line_error(i + 1, &err, 2);
+                    }
+
+                    Data::File(value.to_string())
+                }
+                _ => Data::Str(value.to_string()),
+            };
+
+            commands.push(Command::Set(field, data));
+        }
+
+        files.push((file, commands));
+    }
+
+    Ok(files)
+}
+
+/// The pattern `--parse-from-directory-structure` uses when `--path-pattern` isn't given
+const DEFAULT_PATH_PATTERN: &str = "{artist}/{album}/{track} - {title}";
+
+/// Splits a `--path-pattern` into its levels, and each level into text and the fields in braces.
+/// On failure, what was wrong with it is returned along with the error code to use.
+fn parse_path_pattern(pattern: &str) -> Result<Vec<Vec<PathToken>>, (String, i32)> {
+    let mut levels = Vec::new();
+
+    for level in pattern.split('/').filter(|l| !l.is_empty()) {
+        let mut tokens = Vec::new();
+        let mut rest = level;
+
+        while !rest.is_empty() {
+            if let Some(inner) = rest.strip_prefix('{') {
+                let (name, after) = match inner.split_once('}') {
+                    Some(split) => split,
+                    None => return Err(("a `{` isn't closed".to_string(), 1)),
+                };
+
+                let field = match str_to_field(name) {
+                    Some(Field::Image) => return Err(("the image can't come from a path".to_string(), 1)),
+                    Some(f) => f,
+                    None => return Err((format!("'{}' isn't a field", name), 4)),
+                };
+
+                // There'd be no telling where one field ends and the next starts
+                if let Some(PathToken::Field(_)) = tokens.last() {
+                    return Err(("fields need some text between them".to_string(), 1));
+                }
+
+                tokens.push(PathToken::Field(field));
+                rest = after;
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                tokens.push(PathToken::Text(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        levels.push(tokens);
+    }
+
+    if levels.is_empty() {
+        return Err(("it's empty".to_string(), 1));
+    }
+
+    Ok(levels)
+}
+
+/// Matches a single directory or file name against one level of a path pattern. Each field takes
+/// everything up to the first place the text after it shows up.
+fn match_path_level<'a>(tokens: &'a [PathToken], name: &str) -> Option<Vec<(&'a Field, String)>> {
+    let mut values = Vec::new();
+    let mut rest = name;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            PathToken::Text(t) => rest = rest.strip_prefix(t.as_str())?,
+            PathToken::Field(f) => {
+                let end = match tokens.get(i + 1) {
+                    Some(PathToken::Text(t)) => rest.find(t.as_str())?,
+                    _ => rest.len(),
+                };
+
+                values.push((f, rest[..end].trim().to_string()));
+                rest = &rest[end..];
+            }
+        }
+    }
+
+    if rest.is_empty() { Some(values) } else { None }
+}
+
+/// Matches the end of a file's path, without its extension, against a path pattern. `None` when
+/// the path doesn't fit the pattern. A path that isn't on disk, like the name of a file held in
+/// memory, is matched as it's written.
+fn match_path<'a>(f: &str, levels: &'a [Vec<PathToken>]) -> Option<Vec<(&'a Field, String)>> {
+    let path = fs::canonicalize(f).unwrap_or_else(|_| PathBuf::from(f));
+    let mut names: Vec<String> = path.parent()?.iter().map(|c| c.to_string_lossy().into_owned()).collect();
+    names.push(path.file_stem()?.to_string_lossy().into_owned());
+
+    let names = names.get(names.len().checked_sub(levels.len())?..)?;
+    let mut values = Vec::new();
+
+    for (tokens, name) in levels.iter().zip(names) {
+        values.extend(match_path_level(tokens, name)?);
+    }
+
+    Some(values)
+}
+
+/// The fields that hold free text, and so can pick up stray characters
+const TEXT_FIELDS: [Field; 7] = [Field::Title, Field::Artist, Field::Album, Field::AlbumArtist, Field::Genre, Field::Composer, Field::Comment];
+
+/// Whether a character garbles how text is shown: a byte order mark, or a control character other
+/// than a tab or line break
+fn is_stray(c: char) -> bool {
+    matches!(c, '\u{FEFF}' | '\u{FFFE}') || (c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+}
+
+/// The kinds of stray characters in `s`, for `--print-bom-issues`
+fn stray_kinds(s: &str) -> Vec<&'static str> {
+    let mut kinds = Vec::new();
+
+    if s.contains(['\u{FEFF}', '\u{FFFE}']) {
+        kinds.push("byte order mark");
+    }
+    if s.contains('\0') {
+        kinds.push("null byte");
+    }
+    if s.chars().any(|c| c != '\0' && c.is_control() && is_stray(c)) {
+        kinds.push("control character");
+    }
+
+    kinds
+}
+
+/// Whether a word is one of the ways of writing "featuring", ignoring an opening bracket before it
+fn is_feat(word: &str) -> bool {
+    let word = word.trim_start_matches(['(', '[']).to_lowercase();
+    matches!(word.as_str(), "feat" | "feat." | "ft" | "ft." | "featuring")
+}
+
+/// Rewrites every way of writing "featuring" in `s` as `style`, keeping any bracket in front of it
+fn normalize_feat(s: &str, style: &str) -> String {
+    let words: Vec<String> = s.split(' ').map(|w| {
+        if is_feat(w) {
+            let bracket = &w[..w.len() - w.trim_start_matches(['(', '[']).len()];
+            format!("{}{}", bracket, style)
+        } else {
+            w.to_string()
+        }
+    }).collect();
+
+    words.join(" ")
+}
+
+/// Splits the featured artists out of a title, like `Song (feat. Someone) [Live]` into
+/// `Song [Live]` and `Someone`. `None` when the title doesn't feature anyone.
+fn split_feat(title: &str) -> Option<(String, String)> {
+    let mut start = 0;
+
+    for word in title.split(' ') {
+        if is_feat(word) {
+            break;
+        }
+        start += word.len() + 1;
+    }
+
+    let word = title.get(start..)?.split(' ').next()?;
+    let before = &title[..start];
+    let rest = title[start + word.len()..].trim_start();
+
+    // A bracket opened in front of the word closes after the featured artists
+    let (featured, after) = match word.chars().next() {
+        Some(open @ '(') | Some(open @ '[') => {
+            let close = if open == '(' { ')' } else { ']' };
+            match rest.find(close) {
+                Some(i) => (&rest[..i], &rest[i + 1..]),
+                None => (rest, ""),
+            }
+        }
+        _ => (rest, ""),
+    };
+
+    let featured = featured.trim();
+
+    if featured.is_empty() {
+        return None;
+    }
+
+    let title = format!("{} {}", before.trim_end(), after.trim_start());
+    Some((title.trim().to_string(), featured.to_string()))
+}
+
+/// The number at the start of a file's name, like the `7` in `07 Song.mp3`
+fn leading_number(path: &str) -> Option<u32> {
+    let stem = Path::new(path).file_stem()?.to_str()?;
+    let digits: String = stem.chars().take_while(|c| c.is_ascii_digit()).collect();
+
+    match digits.parse::<u32>() {
+        Ok(0) | Err(_) => None,
+        Ok(n) => Some(n),
+    }
+}
+
+/// The cover files `--auto-cover` looks for, in order, when no `--cover-name` is given
+const DEFAULT_COVER_NAMES: [&str; 6] = ["folder.jpg", "cover.jpg", "front.jpg", "folder.png", "cover.png", "front.png"];
+
+/// Finds the first of `names` in the same directory as `f`, ignoring case, for `--auto-cover`
+fn find_cover(f: &str, names: &[String]) -> Option<PathBuf> {
+    let dir = match Path::new(f).parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+
+    let entries: Vec<PathBuf> = fs::read_dir(dir).ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+
+    names.iter().find_map(|name| {
+        entries.iter().find(|p| {
+            p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.eq_ignore_ascii_case(name))
+        }).cloned()
+    })
+}
+
+/// Reads the front cover out of `source`, for `--copy-cover-only`
+fn read_cover(source: &str, name: &str, opts: &Options) -> Result<Cover, Error> {
+    let mut tag = match inner::read(source) {
+        Ok(t) => t,
+        Err(_) => {
+            let err_str = format!("Failure to open `{}` to copy its cover from", source);
+            return Err(Error::new(name, opts, Some(&err_str), 7));
+        }
+    };
+
+    let (data, mime_type) = match tag.album_cover() {
+        Some(p) => (p.data.to_vec(), p.mime_type),
+        None => {
+            let err_str = format!("`{}` has no cover to copy", source);
+            return Err(Error::new(name, opts, Some(&err_str), 2));
+        }
+    };
+
+    let description = inner::cover_description(&mut *tag).unwrap_or(None);
+
+    Ok(Cover { data, mime_type, description })
+}
+
+/// Turns every field that's set in `source` into a command setting it, for `--copy-from`. Fields
+/// in `skip` are left out.
+fn read_copy_source(source: &str, skip: &[Field], name: &str, opts: &Options) -> Result<Vec<Command>, Error> {
+    let mut tag = match inner::read(source) {
+        Ok(t) => t,
+        Err(_) => {
+            let err_str = format!("Failure to open `{}` to copy its tags from", source);
+            return Err(Error::new(name, opts, Some(&err_str), 7));
+        }
+    };
+    let tag = &mut *tag;
+
+    let mut commands = Vec::new();
+
+    for field in &PRINTOUT_FIELDS {
+        if skip.contains(field) {
+            continue;
+        }
+
+        let data = match field {
+            Field::Track => tag.track_number().map(|n| Data::Int(n as i32)),
+            Field::Disc => tag.disc_number().map(|n| Data::Int(n as i32)),
+            Field::Year => tag.year().map(Data::Int),
+            Field::Title => tag.title().map(|s| Data::Str(s.to_string())),
+            Field::Artist => tag.artist_str().map(|s| Data::Str(s.to_string())),
+            Field::Album => tag.album_title().map(|s| Data::Str(s.to_string())),
+            Field::AlbumArtist => tag.album_artist_str().map(|s| Data::Str(s.to_string())),
+            Field::Genre => inner::genre(tag).ok().flatten().map(Data::Str),
+            Field::Composer => inner::composer(tag).ok().flatten().map(Data::Str),
+            Field::Comment => inner::comment(tag).ok().flatten().map(Data::Str),
+            Field::Image => {
+                // Copied as a cover, to keep its format and description
+                if let Some(p) = tag.album_cover() {
+                    let (data, mime_type) = (p.data.to_vec(), p.mime_type);
+                    let description = inner::cover_description(tag).unwrap_or(None);
+                    commands.push(Command::CopyCover(Cover { data, mime_type, description }));
+                }
+                None
+            }
+        };
+
+        if let Some(data) = data {
+            commands.push(Command::Set(*field, data));
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Parses a track or disc number for the `field` option, either on its own or along with its
+/// total, like `3/12`
+fn parse_number_of(s: &str, field: &str, name: &str, opts: &Options) -> Result<Data, Error> {
+    match s.split_once('/') {
+        Some((n, total)) => match (n.trim().parse::<i32>(), total.trim().parse::<u32>()) {
+            (Ok(n), Ok(total)) if n >= 1 && n as u32 <= total => Ok(Data::IntOf(n, total)),
+            (Ok(n), Ok(total)) => {
+                let err_str = format!("The {} number {} needs to be from 1 up to its total of {}", field, n, total);
+                Err(Error::new(name, opts, Some(&err_str), 3))
+            }
+            _ => {
+                let err_str = format!("'{}' needs to be an integer, or two in the form NUM/TOTAL like 3/12", field);
+                Err(Error::new(name, opts, Some(&err_str), 3))
+            }
+        },
+        None => match s.trim().parse::<i32>() {
+            Ok(i) => Ok(Data::Int(i)),
+            Err(_) => {
+                let err_str = format!("'track', 'year', and 'disc' feeds need to be integers. (Error on '{}' field)", field);
+                Err(Error::new(name, opts, Some(&err_str), 3))
+            }
+        },
+    }
+}
+
+/// The calendar year (UTC) that `secs` seconds after the Unix epoch falls in. Follows Howard
+/// Hinnant's `civil_from_days`, which counts in 400 year eras starting on March 1st so leap days
+/// land at the end of each year.
+fn year_of(secs: u64) -> i32 {
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+
+    // Months are counted from March, so January and February (10 and 11) are in the next year
+    (era * 400 + year_of_era + if month >= 10 { 1 } else { 0 }) as i32
+}
+
+/// The range of years that can be set, as given by `--year-range`. Defaults to 1900 through next
+/// year, and is `None` when `--no-year-range` turns the check off.
+fn year_range(matches: &Matches, name: &str, opts: &Options) -> Result<Option<(i32, i32)>, Error> {
+    if matches.opt_present("no-year-range") {
+        return Ok(None);
+    }
+
+    let s = match matches.opt_str("year-range") {
+        Some(s) => s,
+        None => {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            return Ok(Some((1900, year_of(secs) + 1)));
+        }
+    };
+
+    let range = s.split_once(':').and_then(|(min, max)| {
+        Some((min.trim().parse::<i32>().ok()?, max.trim().parse::<i32>().ok()?))
+    });
+
+    match range {
+        Some((min, max)) if min <= max => Ok(Some((min, max))),
+        _ => {
+            let err_str = format!("'year-range' needs to be two integers in the form MIN:MAX, got '{}'", s);
+            Err(Error::new(name, opts, Some(&err_str), 3))
+        }
+    }
+}
+
+/// Turns the field and `--clear` options into the list of commands they describe
+fn parse_commands(matches: &Matches, years: Option<(i32, i32)>, name: &str, opts: &Options) -> Result<Vec<Command>, Error> {
+    let mut commands: Vec<Command> = Vec::new();
+
+    // Integer Fields
+
+    if matches.opt_present("track") {
+        if let Some(s) = matches.opt_str("track") {
+             commands.push(Command::Set(Field::Track, parse_number_of(&s, "track", name, opts)?));
+        } else {
+            commands.push(Command::Print(Field::Track));
+        }
+    }
+
+    if matches.opt_present("year") {
+        if let Some(s) = matches.opt_str("year") {
+             let val = match s.trim().parse::<i32>() {
+                 Ok(i) => i,
+                 Err(_) => { 
+                     let err_str = "'track', 'year', and 'disc' feeds need to be integers. (Error on 'year' field)";
+                     return Err(Error::new(name, opts, Some(err_str), 3));
+                 }
+             };
+             if let Some((min, max)) = years {
+                 if val < min || val > max {
+                     let err_str = format!("The year {} is outside of the allowed range {}:{}. Use --no-year-range if it's really meant", val, min, max);
+                     return Err(Error::new(name, opts, Some(&err_str), 3));
+                 }
+             }
+             commands.push(Command::Set(Field::Year, Data::Int(val)));
+        } else {
+            commands.push(Command::Print(Field::Year));
+        }
+    }
+
+    if matches.opt_present("disc") {
+        if let Some(s) = matches.opt_str("disc") {
+             commands.push(Command::Set(Field::Disc, parse_number_of(&s, "disc", name, opts)?));
+        } else {
+            commands.push(Command::Print(Field::Disc));
+        }
+    }
+
+    if matches.opt_present("normalize-track-from-filename") {
+        commands.push(Command::TrackFromFilename);
+    }
+
+    if matches.opt_present("parse-from-directory-structure") {
+        let pattern = matches.opt_str("path-pattern").unwrap_or_else(|| DEFAULT_PATH_PATTERN.to_string());
+
+        match parse_path_pattern(&pattern) {
+            Ok(levels) => commands.push(Command::ParsePath(levels)),
+            Err((err, code)) => {
+                let err_str = format!("Bad path pattern '{}': {}", pattern, err);
+                return Err(Error::new(name, opts, Some(&err_str), code));
+            }
+        }
+    }
+
+    // String Fields
+
+    if matches.opt_present("title") {
+        if let Some(s) = matches.opt_str("title") {
+            commands.push(Command::Set(Field::Title, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::Title));
+        }
+    }
+
+    if matches.opt_present("artist") {
+        if let Some(s) = matches.opt_str("artist") {
+            commands.push(Command::Set(Field::Artist, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::Artist));
+        }
+
+    }
+
+    if matches.opt_present("album") {
+        if let Some(s) = matches.opt_str("album") {
+            commands.push(Command::Set(Field::Album, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::Album));
+        }
+
+    }
+
+    if matches.opt_present("albumartist") {
+        if let Some(s) = matches.opt_str("albumartist") {
+            commands.push(Command::Set(Field::AlbumArtist, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::AlbumArtist));
+        }
+
+    }
+
+    if matches.opt_present("genre") {
+        if let Some(s) = matches.opt_str("genre") {
+            commands.push(Command::Set(Field::Genre, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::Genre));
+        }
+
+    }
+
+    if matches.opt_present("composer") {
+        if let Some(s) = matches.opt_str("composer") {
+            commands.push(Command::Set(Field::Composer, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::Composer));
+        }
+
+    }
+
+    if matches.opt_present("comment") {
+        if let Some(s) = matches.opt_str("comment") {
+            commands.push(Command::Set(Field::Comment, Data::Str(s)));
+        } else {
+            commands.push(Command::Print(Field::Comment));
+        }
+
+    }
+
+    // File Fields
+    
+    if matches.opt_present("image") {
+        if let Some(s) = matches.opt_str("image") {
+             if s != "-" { // If we shouldn't read from stdin
+                 // Relative paths are resolved against --image-base, or the working directory
+                 let s = match matches.opt_str("image-base") {
+                     Some(base) => Path::new(&base).join(&s).to_string_lossy().into_owned(),
+                     None => s,
+                 };
+
+                 if !(Path::new(&s).is_file()) {
+                     let err_str = format!("File {} does not exist, is a broken symlink, or we may not have valid permissions", &s);
+                     return Err(Error::new(name, opts, Some(&err_str), 2));
+                 } else {
+                     commands.push(Command::Set(Field::Image, Data::File(s)));
+                 }
+             } else {
+                 commands.push(Command::Set(Field::Image, Data::StdIn));
+             }
+        } else {
+            commands.push(Command::Print(Field::Image));
+        }
+
+    }
+
+    if let Some(path) = matches.opt_str("extract-image") {
+        if matches.free.len() > 1 || matches.free.iter().any(|f| Path::new(f).is_dir()) {
+            let err_str = "'extract-image' writes to a single path, so it only works with one file. Use 'export-image-all' for more";
+            return Err(Error::new(name, opts, Some(err_str), 1));
+        }
+
+        commands.push(Command::ExtractImage(path));
+    }
+
+    if let Some(s) = matches.opt_str("set-from-stdin") {
+        let field = match str_to_field(&s) {
+            Some(f @ Field::Title) | Some(f @ Field::Artist) | Some(f @ Field::Album) | Some(f @ Field::AlbumArtist) | Some(f @ Field::Genre) | Some(f @ Field::Composer) | Some(f @ Field::Comment) => f,
+            Some(_) => {
+                let err_str = "Only 'title', 'artist', 'album', 'albumartist', 'genre', 'composer', and 'comment' can be set from stdin";
+                return Err(Error::new(name, opts, Some(err_str), 1));
+            }
+            None => {
+                let err_str = format!("Cannot set '{}' field because it does not exist!", &s);
+                return Err(Error::new(name, opts, Some(&err_str), 4));
+            }
+        };
+
+        if matches.opt_present(field_to_str(&field)) {
+            let err_str = format!("Cannot use 'set-from-stdin' and '{}' at the same time", field_to_str(&field));
+            return Err(Error::new(name, opts, Some(&err_str), 1));
+        }
+
+        if matches.opt_str("image").as_deref() == Some("-") {
+            let err_str = "Only one field can be read from stdin";
+            return Err(Error::new(name, opts, Some(err_str), 1));
+        }
+
+        commands.push(Command::Set(field, Data::StdIn));
+    }
+
+    if let Some(source) = matches.opt_str("copy-cover-only") {
+        if matches.opt_present("image") {
+            let err_str = "Cannot use 'copy-cover-only' and 'image' at the same time";
+            return Err(Error::new(name, opts, Some(err_str), 1));
+        }
+
+        commands.push(Command::CopyCover(read_cover(&source, name, opts)?));
+    }
+
+    if matches.opt_present("auto-cover") {
+        for other in &["image", "copy-cover-only"] {
+            if matches.opt_present(other) {
+                let err_str = format!("Cannot use 'auto-cover' and '{}' at the same time", other);
+                return Err(Error::new(name, opts, Some(&err_str), 1));
+            }
+        }
+
+        let mut names = matches.opt_strs("cover-name");
+        if names.is_empty() {
+            names = DEFAULT_COVER_NAMES.iter().map(|n| n.to_string()).collect();
+        }
+
+        commands.push(Command::AutoCover(names));
+    }
+
+    if matches.opt_present("split-various-artists") {
+        if matches.opt_present("albumartist") {
+            let err_str = "Cannot use 'split-various-artists' and 'albumartist' at the same time";
+            return Err(Error::new(name, opts, Some(err_str), 1));
+        }
+
+        commands.push(Command::SplitVariousArtists);
+    }
+
+    // Genres
+
+    if let Some(s) = matches.opt_str("genre-id") {
+        if matches.opt_present("genre") {
+            let err_str = "Cannot use 'genre-id' and 'genre' at the same time";
+            return Err(Error::new(name, opts, Some(err_str), 1));
+        }
+
+        match s.trim().parse::<usize>().ok().and_then(|i| genres::GENRES.get(i)) {
+            Some(g) => commands.push(Command::Set(Field::Genre, Data::Str(g.to_string()))),
+            None => {
+                let err_str = format!("'genre-id' needs to be one of the IDs from --print-genre-list (0 to {}), got '{}'", genres::GENRES.len() - 1, s);
+                return Err(Error::new(name, opts, Some(&err_str), 3));
+            }
+        }
+    }
+
+    if let Some(file) = matches.opt_str("genre-map") {
+        let contents = match fs::read_to_string(&file) {
+            Ok(c) => c,
+            Err(_) => {
+                let err_str = format!("Failed to read genre map {}", file);
+                return Err(Error::new(name, opts, Some(&err_str), 2));
+            }
+        };
+
+        match parse_genre_map(&contents) {
+            Ok(map) => commands.push(Command::MapGenre(map)),
+            Err(line) => {
+                let err_str = format!("Line {} of genre map {} isn't a `from = to` pair", line, file);
+                return Err(Error::new(name, opts, Some(&err_str), 1));
+            }
+        }
+    }
+
+    // Raw keys
+
+    for key in matches.opt_strs("print-raw") {
+        commands.push(Command::PrintRaw(key));
+    }
+
+    for key in matches.opt_strs("strip-frame") {
+        commands.push(Command::StripFrame(key));
+    }
+
+    if matches.opt_present("merge-comments") {
+        let separator = matches.opt_str("comment-separator").unwrap_or_else(|| "; ".to_string());
+        commands.push(Command::MergeComments(separator));
+    }
+
+    if matches.opt_present("normalize-feat") {
+        commands.push(Command::NormalizeFeat {
+            style: matches.opt_str("feat-style").unwrap_or_else(|| "feat.".to_string()),
+            move_to_artist: matches.opt_present("move-feat-to-artist"),
+        });
+    } else if matches.opt_present("move-feat-to-artist") {
+        let err_str = "'move-feat-to-artist' only works along with 'normalize-feat'";
+        return Err(Error::new(name, opts, Some(err_str), 1));
+    }
+
+    if matches.opt_present("strip-control-chars") {
+        commands.push(Command::StripControlChars);
+    }
+
+    if let Some(s) = matches.opt_str("field-max-values") {
+        match s.trim().parse::<usize>() {
+            Ok(max) if max > 0 => commands.push(Command::CapValues(max)),
+            _ => {
+                let err_str = "'field-max-values' needs to be an integer above 0";
+                return Err(Error::new(name, opts, Some(err_str), 3));
+            }
+        }
+    }
+
+    if matches.opt_present("reencode-utf8") {
+        // Only ID3v2.4 can hold UTF-8
+        if matches.opt_str("id3-version").as_deref().map(str::trim) == Some("2.3") {
+            let err_str = "Cannot use 'reencode-utf8' with ID3v2.3, which can't hold UTF-8";
+            return Err(Error::new(name, opts, Some(err_str), 1));
+        }
+
+        commands.push(Command::ReencodeUtf8);
+    }
+
+    // Copying from another file
+
+    if let Some(source) = matches.opt_str("copy-from") {
+        // Fields given with their own options, or cleared, take the place of the copied ones
+        let mut skip: Vec<Field> = commands.iter().filter_map(command_field).copied().collect();
+        skip.extend(matches.opt_strs("clear").iter().filter_map(|s| str_to_field(s)));
+
+        commands.extend(read_copy_source(&source, &skip, name, opts)?);
+    }
+
+    // Clear option
+
+    let mut used: HashSet<&Field> = HashSet::new();
+    let mut clear_commands: Vec<Command> = Vec::new();
+
+    for c in &commands { // Find all of the fields for the set commands
+        match c {
+            Command::Set(f, _) => { used.insert(f); }
+            Command::Print(f) => { used.insert(f); }
+            Command::TrackFromFilename => { used.insert(&Field::Track); }
+            Command::ParsePath(levels) => {
+                for token in levels.iter().flatten() {
+                    if let PathToken::Field(f) = token {
+                        used.insert(f);
+                    }
+                }
+            }
+            Command::SplitVariousArtists => { used.insert(&Field::AlbumArtist); }
+            Command::CopyCover(_) | Command::AutoCover(_) | Command::ExtractImage(_) => { used.insert(&Field::Image); }
+            Command::Clear(_) | Command::ClearAll | Command::MapGenre(_) | Command::PrintRaw(_) | Command::StripFrame(_) | Command::ReencodeUtf8 | Command::MergeComments(_) | Command::CapValues(_) | Command::NormalizeFeat { .. } | Command::StripControlChars => { /* no-op */ },
+        }
+    }
+
+    let mut clear_all = false;
+
+    for s in matches.opt_strs("clear") { // For every clear command...
+        if s == "all" {
+            clear_all = true;
+        } else if let Some(f) = str_to_field(&s) {
+            if !(used.contains(&f)) { // If the field isn't in used in a set command
+                clear_commands.push(Command::Clear(f)); // Then add a clear command
+            } else { // If the field is in the set command, error.
+                let err_str = format!("Cannot clear and set/print field '{}' at the same time", &s);
+                return Err(Error::new(name, opts, Some(&err_str), 5));
+            }
+        } else { // If the clear command didn't contain a valid field, error.
+            let err_str = format!("Cannot clear '{}' field because it does not exist!", &s);
+            return Err(Error::new(name, opts, Some(&err_str), 4));
+        }
+    }
+
+    for c in clear_commands {
+        commands.push(c);
+    }
+
+    if clear_all {
+        commands.insert(0, Command::ClearAll);
+    }
+
+    Ok(commands)
+}
+
+/// Where presets are read from when `--preset-file` isn't given
+fn default_preset_file() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir).join("insignia").join("presets"));
+    }
+
+    env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".config").join("insignia").join("presets"))
+}
+
+/// Finds the arguments that a preset expands into. A presets file is made up of `[name]` headers,
+/// each followed by the preset's arguments, one per line (e.g. `--year=2021`). Blank lines and
+/// lines starting with `#` are ignored.
+fn find_preset(contents: &str, preset: &str) -> Option<Vec<String>> {
+    let mut args: Option<Vec<String>> = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            if args.is_some() { // We've reached the end of the preset
+                break;
+            }
+            if &line[1..line.len() - 1] == preset {
+                args = Some(Vec::new());
+            }
+        } else if let Some(a) = &mut args {
+            a.push(line.to_string());
+        }
+    }
+
+    args
+}
+
+/// Everything `--roundtrip-test` compares in a tag: the printout fields, then every frame when the
+/// tag format lets them be listed. Binary values are base64 encoded. The id3 crate merges frames
+/// that repeat, so ID3v2 frames are also counted from the file at `path` itself.
+fn roundtrip_values(tag: &mut dyn AudioTag, path: &str) -> Vec<(String, String)> {
+    let mut values: Vec<(String, String)> = PRINTOUT_FIELDS.iter()
+        .map(|f| (field_to_str(f).to_string(), field_value(tag, f, &Padding::default())))
+        .collect();
+
+    values.push(("image data".to_string(), tag.album_cover().map(|p| base64(p.data)).unwrap_or_default()));
+
+    if let Ok(frames) = inner::all(tag) {
+        for (key, value) in frames {
+            let value = match value {
+                inner::RawValue::Text(s) => s,
+                inner::RawValue::Binary { data, .. } => base64(&data),
+            };
+            values.push((key, value));
+        }
+    }
+
+    if let Some(keys) = fs::read(path).ok().and_then(|d| encoding::frame_keys(&d)) {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+
+        for key in keys {
+            match counts.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((key, 1)),
+            }
+        }
+
+        values.extend(counts.into_iter().map(|(key, n)| (format!("{} frame count", key), n.to_string())));
+    }
+
+    values
+}
+
+/// A copy of some data in the system's temporary directory, deleted once dropped
+struct TempFile {
+    path: String,
+}
+
+/// A file name that no other temporary file of this run has
+fn temp_name() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    format!("insignia-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::SeqCst))
+}
+
+impl TempFile {
+    fn new(data: &[u8]) -> io::Result<TempFile> {
+        let path = env::temp_dir().join(temp_name()).to_string_lossy().into_owned();
+        fs::write(&path, data)?;
+
+        Ok(TempFile { path })
+    }
+
+    /// Copies the file at `original` to a hidden file in the same directory, so that it can later
+    /// be renamed over the original without crossing filesystems
+    fn beside(original: &str) -> io::Result<TempFile> {
+        let original = Path::new(original);
+        let file_name = format!(".{}.{}", original.file_name().unwrap_or_default().to_string_lossy(), temp_name());
+        let path = original.with_file_name(file_name).to_string_lossy().into_owned();
+        fs::copy(original, &path)?;
+
+        Ok(TempFile { path })
+    }
+
+    /// Moves the file over the one at `to`, replacing it
+    fn persist(self, to: &str) -> io::Result<()> {
+        fs::rename(&self.path, to)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn printout_properties(properties: &Properties) -> String {
+    fn or_unknown<T: ToString>(v: Option<T>) -> String {
+        v.map(|v| v.to_string()).unwrap_or_else(|| "Unknown".to_string())
+    }
+
+    let mut result = String::new();
+    result.push_str(&format!("Duration: {}\n", or_unknown(properties.duration.map(|d| format!("{:.3}", d.as_secs_f64())))));
+    result.push_str(&format!("Bitrate: {}\n", or_unknown(properties.bitrate)));
+    result.push_str(&format!("Sample Rate: {}\n", or_unknown(properties.sample_rate)));
+    result.push_str(&format!("Channels: {}\n", or_unknown(properties.channels)));
+
+    result
+}
+
+/// Whether `--jobs` modified a file, along with what it printed
+type JobResult = (Result<bool, Error>, Vec<u8>);
+
+/// Applies a single command to a tag that's already open, writing anything it prints to `out`.
+/// Returns whether the tag was changed and needs to be written. Commands that need the file the tag
+/// came from (`TrackFromFilename`, `ParsePath`, `AutoCover`, and `ReencodeUtf8`) fail with code 1,
+/// since there's no file to go on; `Config::exec_bytes` runs those against a named file instead.
+/// Nothing else is printed, since what changed is in the tag itself.
+pub fn apply_command(tag: &mut dyn AudioTag, command: &Command, out: &mut dyn Write) -> Result<bool, Error> {
+    let mut config = Config::parse(&[], "insignia", false)?;
+    config.warnings_to(io::sink());
+
+    let needs_file = match command {
+        Command::TrackFromFilename => "TrackFromFilename",
+        Command::ParsePath(_) => "ParsePath",
+        Command::AutoCover(_) => "AutoCover",
+        Command::ReencodeUtf8 => "ReencodeUtf8",
+        _ => return config.apply_command(tag, command, "", "", out),
+    };
+
+    let err_str = format!("{} works from the file a tag is in, so it can't be applied to a tag on its own", needs_file);
+    Err(Error::new(&config.name, &config.opts, Some(&err_str), 1))
+}
+
+impl Config {
+    /// Parses arguments and creates a Config struct
+    pub fn new(args: &[String], name: &str) -> Result<Config, Error> { 
+        Config::parse(args, name, true)
+    }
+
+    /// Parses arguments like `Config::new`, but doesn't require any files to be specified. Meant
+    /// to be used alongside `Config::exec_bytes`.
+    pub fn new_in_memory(args: &[String], name: &str) -> Result<Config, Error> {
+        Config::parse(args, name, false)
+    }
+
+    /// Makes a config that runs `commands` on `files`, with every other option left at its default.
+    /// Fails with an error code of `1` when a command sets a field to the wrong kind of data, like
+    /// a track set to a string.
+    pub fn from_commands(commands: Vec<Command>, files: Vec<String>) -> Result<Config, Error> {
+        let mut config = Config::parse(&[], "insignia", false)?;
+
+        for c in &commands {
+            if let Command::Set(f, d) = c {
+                if !data_fits(f, d) {
+                    let kind = match d {
+                        Data::Str(_) => "a string",
+                        Data::Int(_) => "a number",
+                        Data::IntOf(..) => "a number out of a total",
+                        Data::File(_) => "a file",
+                        Data::StdIn => "stdin",
+                        Data::Bytes(_) => "bytes",
+                    };
+                    let err_str = format!("{} can't be set to {}", field_to_str(f), kind);
+                    return Err(Error::new(&config.name, &config.opts, Some(&err_str), 1));
+                }
+            }
+        }
+
+        config.commands = commands;
+        config.files = files;
+
+        Ok(config)
+    }
+
+    /// Sends warnings and messages about what's being done to `warnings` instead of stderr, so they
+    /// can be kept or, with `io::sink()`, left out. What's asked to be printed still goes to the
+    /// writer given to `exec_to`.
+    pub fn warnings_to(&mut self, warnings: impl Write + Send + 'static) {
+        self.warnings = Mutex::new(Box::new(warnings));
+    }
+
+    fn parse(args: &[String], name: &str, require_files: bool) -> Result<Config, Error> {
+       let mut opts = Options::new();
+
+       // Flags
+       opts.optflag("h", "help", "Print this help text");
+       opts.optflag("V", "version", "Print version information");
+       opts.optflag("", "print-genre-list", "Print the standard ID3v1 genres along with their numeric IDs");
+       opts.optflag("k", "keep-going", "Keep going when a file fails instead of stopping, and report the failures at the end");
+       opts.optflag("", "continue-on-error", "The same as --keep-going");
+       opts.optflag("", "validate-image", "Fully decode an image before embedding it, to catch corrupt or truncated images");
+       opts.optopt("", "extract-image", "Write the image to this path, instead of printing it like `--image` does", "PATH");
+       opts.optflag("", "dump-image", "Write the image of every file next to it, named after the file with the image's extension, like `song.jpg` for `song.flac`");
+       opts.optopt("", "export-image-all", "Export the image of every file into this directory, named after the file. An existing file isn't replaced unless `--force` is given", "DIR");
+       opts.optflag("", "write-cover-to-sidecar", "Also write each file's cover next to it as folder.jpg (or folder.png, and so on), once every other option has run. An existing file isn't replaced unless `--force` is given");
+       opts.optopt("", "sidecar-name", "The name `--write-cover-to-sidecar` writes covers to instead of folder.jpg", "NAME");
+       opts.optflag("", "force", "Replace fields that are already set with `--parse-from-directory-structure`, and existing files with `--write-cover-to-sidecar`, `--dump-image`, or `--export-image-all`");
+       opts.optflagopt("", "print-relative", "Show the paths of files relative to a directory, the current one by default, wherever they're printed, including JSON and CSV output", "BASE");
+       opts.optflag("", "print-path", "Start each file's output with its path, to tell which output belongs to which file");
+       opts.optflag("", "print-modified-only", "Instead of printing each file's tags after editing them, list just the files whose tags changed and what changed");
+       opts.optflag("", "print-changed-fields", "Instead of printing each file's tags after editing them, list the names of the fields that changed in each file, like `song.mp3: title, artist`");
+       opts.optflag("q", "quiet", "Don't print each file's tags after editing it. Fields asked for with their options are still printed, and files are still printed when nothing is being edited");
+       opts.optflag("", "no-default-print", "Don't print each file's tags when no other output was asked for. Warnings and errors are still shown");
+       opts.optflag("", "require-image", "Fail on files that don't have a cover once every other option has run, to find the ones missing one");
+       opts.optflag("", "confirm", "Describe the changes and ask before making them. When stdin isn't a terminal, `--yes` is needed to go ahead");
+       opts.optflag("y", "yes", "Go ahead with the changes `--confirm` asks about without asking");
+       opts.optflag("", "export-cue", "Print a CUE sheet for the files, one track per file in the order given");
+       opts.optflag("", "stats", "Print statistics over all of the files: their count and size, how many are missing a cover, how many albums and artists there are, and the average length. With --json, they're printed as a single JSON object");
+       opts.optflag("", "repair", "Move ID3v2 tags that were added to the end of a file to the start, where players look for them");
+       opts.optflag("", "compact", "Show each file's tags on a single line, like `song.mp3: Artist - Title [Album, 2003]`");
+       opts.optflag("", "json", "Show each file's tags as a JSON object, along with its length in seconds, bitrate, sample rate, and channels, and with `null` for whatever isn't known. More than one file gives an array of objects");
+       opts.optopt("", "watch", "Keep watching a directory, and run the field options on each audio file that's added to it once it's done being written. The directory is checked once a second, and a file is edited once its size and modification time are the same between two checks. Runs until it's interrupted", "DIR");
+       #[cfg(feature = "analysis")]
+       opts.optflag("", "apply-replaygain", "Analyze the loudness of the files and write their ReplayGain track values, plus album values treating every file as one album. Only uncompressed mono and stereo WAV files can be analyzed, since there's no decoder for MP3, FLAC, Ogg, or M4A; those files fail with code 7");
+       #[cfg(feature = "analysis")]
+       opts.optopt("", "halt-timeout", "Give up on analyzing a file after this many seconds. With `--keep-going`, the other files are still analyzed", "SECONDS");
+       opts.optflag("", "all-tags-json", "Print every frame in each file's tag as a line of JSON, including ones that aren't fields. Binary values are base64 encoded");
+       opts.optflag("", "print-field-presence", "Print a table of which fields each file has, with a Y or N for each field");
+       opts.optflag("", "roundtrip-test", "Check that each file's tag survives being written back unchanged, using a copy of the file, and report anything that's lost. The files themselves aren't touched");
+       opts.optflag("", "print-bom-issues", "Print each text field that has a byte order mark, null byte, or other control character in it, which can show up garbled");
+       opts.optopt("", "print-version-of", "Print the value of a field in each of a file's ID3v2, ID3v1, and APEv2 tags, next to the value that's shown, and whether they disagree", "FIELD");
+       opts.optflag("", "print-duplicates-by-tag", "Print groups of files that have the same artist, title, and album, which are likely to be duplicates");
+       opts.optmulti("", "duplicate-field", "A field `--print-duplicates-by-tag` groups by instead of artist, title, and album. Can be given more than once", "FIELD");
+       opts.optflag("", "csv", "Print `--print-field-presence` as CSV");
+       opts.optflag("", "print-encoding", "Print the text encoding of each ID3v2 frame, to find Latin-1 frames that may show up garbled");
+       opts.optflag("", "album-only", "Refuse to set a field that's different for each track (track, title, or artist) on more than one file");
+       opts.optopt("", "print-field-json", "Print a field of each file as a line of JSON, with a null value when it isn't set. Images are base64 encoded", "FIELD");
+       opts.optflag("", "print-tag-count", "Print how many frames each file's tag has, and how many there are of each key, marking keys that show up more than once");
+       opts.optflag("", "print-art-info", "Print the format, dimensions, and size in bytes of each file's image");
+       opts.optflag("", "print-hash", "Print a hash of each file's tag, which stays the same between runs as long as the tag does");
+       opts.optflag("", "summary-json", "Print a line of JSON at the end of the run with how many files were processed, modified, unchanged, skipped, and errored, along with each error");
+       opts.optflag("r", "recursive", "When a directory is given, process every audio file in it and its subdirectories. Other files are skipped. Symlinks to files are followed, but symlinks to directories are skipped unless `--follow-symlinks` is given");
+       opts.optflag("", "follow-symlinks", "With `--recursive`, also walk into directories that are symlinked to. Each directory is only walked once, so links that loop back are safe");
+       opts.optflag("n", "dry-run", "Print the changes that would be made to each file, with the old and new value of each field, without writing anything. With `--json`, each file is an object with its `path`, whether it `would_write`, and its `changes` as a list of `field`, `old`, and `new`");
+       opts.optflag("", "transaction", "Edit copies of the files, and only put them in place of the originals once every file succeeds, so that either all of the files are changed or none are");
+       opts.optflag("", "guard-audio-checksum", "Check that writing a file's tag left its audio alone, and put the file back the way it was if it didn't");
+       opts.optflag("", "no-read-after-write", "Don't read files back once they're written, for speed in large batches. Fields the file's tag format dropped go unnoticed, and the usual printout is skipped for written files");
+       opts.optflag("", "properties", "Print the audio properties of each file (duration in seconds, bitrate in kbps, sample rate in Hz, and channels)");
+
+       // Options
+       opts.optmulti("", "clear", "Clear out a field, or every field with `all`. Fields set alongside `all` keep their new values", "FIELD");
+       opts.optflag("", "clear-related", "When clearing the title, artist, album, or album artist, also remove the frames that hold its sort order");
+       opts.optmulti("", "print-raw", "Print the value of a tag key that isn't one of the fields, like an ID3v2 frame ID, MP4 atom name, or APE item key", "KEY");
+       opts.optmulti("", "strip-frame", "Remove every frame with this key, named as for `--print-raw`, leaving the rest of the tag alone", "KEY");
+       opts.optflag("", "merge-comments", "Merge every comment into a single one");
+       opts.optopt("", "comment-separator", "What to join comments with when merging them. Defaults to `; `", "STRING");
+       opts.optflag("", "strip-control-chars", "Remove byte order marks, null bytes, and other control characters from the text fields. Tabs and line breaks are kept");
+       opts.optflag("", "normalize-feat", "Write every \"feat.\", \"ft.\", and \"featuring\" in the artist and title the same way");
+       opts.optopt("", "feat-style", "How `--normalize-feat` writes \"featuring\". Defaults to `feat.`", "STRING");
+       opts.optflag("", "move-feat-to-artist", "With `--normalize-feat`, also move featured artists out of the title, like `Song (feat. Someone)`, and onto the end of the artist");
+       opts.optopt("", "field-max-values", "Keep only the first NUM values of fields that hold several, like artist or genre, dropping the rest", "NUM");
+       opts.optflag("", "reencode-utf8", "Rewrite ID3v2 text frames stored as Latin-1 or UTF-16 as UTF-8, in an ID3v2.4 tag");
+       opts.optmulti("", "only", "Only show these fields when reading, in the order given", "FIELD");
+       opts.optopt("", "field-order", "A comma-separated list of fields to show first when reading, in the order given, like `artist,title,album`. The other fields follow in their usual order", "FIELDS");
+       opts.optflag("", "omit-unlisted", "With `--field-order`, leave out the fields that aren't listed");
+       opts.optopt("", "pad", "Zero-pad the track and disc numbers to this many digits when showing them", "NUM");
+       opts.optopt("", "pad-track", "Zero-pad the track number to this many digits when showing it", "NUM");
+       opts.optopt("", "pad-disc", "Zero-pad the disc number to this many digits when showing it", "NUM");
+       opts.optmulti("", "preset", "Apply a preset from the presets file. Options given explicitly take priority over the preset's", "NAME");
+       opts.optopt("", "id3-version", "The ID3v2 version to write tags in, 2.3 or 2.4. Defaults to 2.4", "VERSION");
+       opts.optflagopt("", "jobs", "Work on NUM files at once, given as `--jobs=NUM`, or on one for each CPU (up to 8) when no number is given. Output is still printed in the order the files are given. Without --keep-going, files already being worked on when one fails are still finished and reported", "NUM");
+       opts.optopt("", "skip", "Skip the first NUM files, in the order they're given. Applied before `--limit`, so the two page through a batch", "NUM");
+       opts.optopt("", "limit", "Only process the first NUM files, in the order they're given", "NUM");
+       opts.optopt("", "tag-size-limit", "Fail on files whose tags take up more than this many bytes, to find files bloated by huge images or junk frames", "BYTES");
+       opts.optopt("", "preset-file", "The presets file to read presets from. Defaults to `$XDG_CONFIG_HOME/insignia/presets`", "FILE");
+
+       // Field Options
+       opts.optflagopt("", "track", "The track number, optionally with the total number of tracks like `3/12`", "NUM");
+       opts.optflagopt("", "year", "The year the track released", "NUM");
+       opts.optopt("", "year-range", "The range of years that can be set, to catch typos. Defaults to 1900 through next year", "MIN:MAX");
+       opts.optflag("", "no-year-range", "Allow setting any year");
+       opts.optflagopt("", "disc", "The disc this track is on, optionally with the total number of discs like `1/2`", "NUM");
+
+       opts.optflag("", "normalize-track-from-filename", "Set the track number from the number the filename starts with, if the track number isn't set yet");
+       opts.optflag("", "parse-from-directory-structure", "Fill in fields that aren't set from the folders a file is in and its name, using `--path-pattern`. With `--force`, fields that are set are replaced too");
+       opts.optopt("", "path-pattern", "The pattern `--parse-from-directory-structure` matches against the end of each file's path, without its extension. Fields go in braces. Defaults to `{artist}/{album}/{track} - {title}`", "PATTERN");
+
+       opts.optflag("", "split-various-artists", "Set the album artist to \"Various Artists\" and mark the files as a compilation, leaving each track's artist alone");
+       opts.optopt("", "genre-id", "Set the genre to the standard ID3v1 genre with this ID, as listed by --print-genre-list", "NUM");
+       opts.optopt("", "genre-map", "Rewrite genres into their canonical forms using a file of `from = to` lines", "FILE");
+       opts.optflag("", "strict", "Report genres that aren't in the genre map");
+       opts.optopt("", "genre-validate", "Print each file whose genre isn't in a file of allowed genres, one per line, without changing anything", "FILE");
+       opts.optflag("", "genre-ignore-case", "Ignore case when checking genres with `--genre-validate`");
+
+       opts.optflagopt("", "title", "The song name", "STRING");
+       opts.optflagopt("", "artist", "The song's artist", "STRING");
+       opts.optflagopt("", "album", "The song's album", "STRING");
+       opts.optflagopt("", "albumartist", "The album artist", "STRING");
+       opts.optflagopt("", "genre", "The song's genre", "STRING");
+       opts.optflagopt("", "composer", "The song's composer", "STRING");
+       opts.optflagopt("", "comment", "A description/comment about the song", "STRING");
+
+       opts.optflagopt("", "image", "The album artwork/photo that goes along with the song. `-` for stdin, `./-` for a file literally named `-`.", "FILE");
+       opts.optopt("", "set-from-stdin", "Read the value of a text field from stdin, without the trailing newline", "FIELD");
+       opts.optopt("", "image-base", "The directory relative `--image` paths are resolved against. Defaults to the current directory", "DIR");
+       opts.optopt("", "copy-from", "Copy every field that's set in another music file, image included. Fields it doesn't have are left alone, and fields given with their own options or cleared aren't copied", "SOURCE");
+       opts.optopt("", "copy-cover-only", "Copy just the front cover from another music file, keeping its description", "SOURCE");
+       opts.optflag("", "auto-cover", "Set the cover of files that don't have one from a cover file in the same folder, like folder.jpg, cover.jpg, or front.jpg");
+       opts.optmulti("", "cover-name", "A cover file name for `--auto-cover` to look for instead of the usual ones. Can be given more than once, and the first one found is used", "NAME");
+       opts.optopt("", "fields-from", "Set fields on each file from a file of `filename<TAB>key=value<TAB>key=value` lines, like a spreadsheet exported as TSV. An empty value clears the field. The files listed are edited along with any others given", "FILE");
+
+       let matches: Matches = match opts.parse(args) {
+            Ok(m) => m,
+            Err(f) => {
+                let err_str = fail_to_str(f);
+                return Err(Error::new(name, &opts, Some(&err_str), 1));
+            }
+       };
+
+       // Flags that end the program, handled before any files are looked at
+       if matches.opt_present("help") {
+            return Err(Error::new(name, &opts, None, 0));
+       }
+
+       if matches.opt_present("version") {
+           let version = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+           return Err(Error::new(name, &opts, Some(&version), 0));
+       }
+
+       // Modes that don't need any files
+       if matches.opt_present("print-genre-list") {
+           let list: Vec<String> = genres::GENRES.iter().enumerate()
+               .map(|(i, g)| format!("{}: {}", i, g))
+               .collect();
+           return Err(Error::new(name, &opts, Some(&list.join("\n")), 0));
+       }
+
+       // Make sure some files are specified
+       if require_files && matches.free.is_empty() && !matches.opt_present("fields-from") && !matches.opt_present("watch") {
+           let error_str = "There were no files specified.";
+           return Err(Error::new(name, &opts, Some(error_str), 6));
+       }
+
+       if matches.opt_present("follow-symlinks") && !matches.opt_present("recursive") {
+           let err_str = "'follow-symlinks' only works along with 'recursive'";
+           return Err(Error::new(name, &opts, Some(err_str), 1));
+       }
+
+       // Verify each file does exist, and swap directories for the audio files in them
+       let mut free = Vec::new();
+       for f in &matches.free {
+           if Path::new(&f).is_dir() {
+               if matches.opt_present("recursive") {
+                   free.extend(audio_files_in(Path::new(&f), matches.opt_present("follow-symlinks")));
+                   continue;
+               }
+
+               let err_str = format!("{} is a directory. Directories aren't searched unless `--recursive` is given, pass the files inside it instead (e.g. `{}/*`)", &f, f.trim_end_matches('/'));
+               return Err(Error::new(name, &opts, Some(&err_str), 2));
+           }
+           if !(Path::new(&f).is_file()) {
+               let err_str = format!("File {} does not exist, is a broken symlink, or we may not have valid permissions", &f);
+               return Err(Error::new(name, &opts, Some(&err_str), 2));
+           }
+
+           free.push(f.clone());
+       }
+
+       if require_files && free.is_empty() && !matches.free.is_empty() && !matches.opt_present("fields-from") {
+           let error_str = "There were no audio files in the directories given.";
+           return Err(Error::new(name, &opts, Some(error_str), 6));
+       }
+
+       // Fields
+       let years = year_range(&matches, name, &opts)?;
+       let mut commands = parse_commands(&matches, years, name, &opts)?;
+
+       // Presets
+       let presets = matches.opt_strs("preset");
+
+       if !presets.is_empty() {
+           let preset_file = match matches.opt_str("preset-file").map(PathBuf::from).or_else(default_preset_file) {
+               Some(p) => p,
+               None => {
+                   let err_str = "Couldn't find a presets file, use `--preset-file` to specify one";
+                   return Err(Error::new(name, &opts, Some(err_str), 2));
+               }
+           };
+
+           let contents = match fs::read_to_string(&preset_file) {
+               Ok(c) => c,
+               Err(_) => {
+                   let err_str = format!("Failed to read presets file {}", preset_file.display());
+                   return Err(Error::new(name, &opts, Some(&err_str), 2));
+               }
+           };
+
+           for p in presets {
+               let preset_args = match find_preset(&contents, &p) {
+                   Some(a) => a,
+                   None => {
+                       let err_str = format!("Preset '{}' isn't defined in {}", p, preset_file.display());
+                       return Err(Error::new(name, &opts, Some(&err_str), 1));
+                   }
+               };
+
+               let preset_matches = match opts.parse(&preset_args) {
+                   Ok(m) => m,
+                   Err(f) => {
+                       let err_str = format!("{} (in preset '{}')", fail_to_str(f), p);
+                       return Err(Error::new(name, &opts, Some(&err_str), 1));
+                   }
+               };
+
+               if !preset_matches.free.is_empty() {
+                   let err_str = format!("Preset '{}' may only contain options, not files", p);
+                   return Err(Error::new(name, &opts, Some(&err_str), 1));
+               }
+
+               // Fields given explicitly, or by an earlier preset, take priority
+               for c in parse_commands(&preset_matches, years, name, &opts)? {
+                   if commands.iter().any(|e| overlaps(e, &c)) {
+                       continue;
+                   }
+
+                   // Still ahead of the commands it'd otherwise undo
+                   if let Command::ClearAll = c {
+                       commands.insert(0, c);
+                   } else {
+                       commands.push(c);
+                   }
+               }
+           }
+       }
+
+       // A preset can ask for stdin as well as the command line
+       if commands.iter().filter(|c| matches!(c, Command::Set(_, Data::StdIn))).count() > 1 {
+           let err_str = "Only one field can be read from stdin";
+           return Err(Error::new(name, &opts, Some(err_str), 1));
+       }
+
+       let mut modes = vec!["export-cue", "stats", "print-field-presence", "print-duplicates-by-tag", "roundtrip-test", "print-bom-issues", "genre-validate", "print-version-of"];
+       if cfg!(feature = "analysis") {
+           modes.push("apply-replaygain");
+       }
+
+       for mode in &modes {
+           if matches.opt_present(mode) && (!commands.is_empty() || matches.opt_present("fields-from")) {
+               let err_str = format!("Cannot use '{}' alongside field options", mode);
+               return Err(Error::new(name, &opts, Some(&err_str), 1));
+           }
+       }
+
+       // Output
+       let mut only: Vec<Field> = Vec::new();
+
+       for s in matches.opt_strs("only") {
+           match str_to_field(&s) {
+               Some(f) => only.push(f),
+               None => {
+                   let err_str = format!("Cannot show '{}' field because it does not exist!", &s);
+                   return Err(Error::new(name, &opts, Some(&err_str), 4));
+               }
+           }
+       }
+
+       let mut field_order: Vec<Field> = Vec::new();
+
+       if let Some(list) = matches.opt_str("field-order") {
+           if !only.is_empty() {
+               let err_str = "Cannot use 'field-order' and 'only' at the same time";
+               return Err(Error::new(name, &opts, Some(err_str), 1));
+           }
+
+           for s in list.split(',').map(str::trim) {
+               match str_to_field(s) {
+                   Some(f) if field_order.contains(&f) => {
+                       let err_str = format!("'{}' is listed more than once in 'field-order'", s);
+                       return Err(Error::new(name, &opts, Some(&err_str), 1));
+                   }
+                   Some(f) => field_order.push(f),
+                   None => {
+                       let err_str = format!("Cannot show '{}' field because it does not exist!", s);
+                       return Err(Error::new(name, &opts, Some(&err_str), 4));
+                   }
+               }
+           }
+       } else if matches.opt_present("omit-unlisted") {
+           let err_str = "'omit-unlisted' only works along with 'field-order'";
+           return Err(Error::new(name, &opts, Some(err_str), 1));
+       }
+
+       let relative_to = if matches.opt_present("print-relative") {
+           let base = matches.opt_str("print-relative").unwrap_or_else(|| ".".to_string());
+
+           match fs::canonicalize(&base) {
+               Ok(b) if b.is_dir() => Some(b),
+               _ => {
+                   let err_str = format!("{} isn't a directory that paths can be shown relative to", base);
+                   return Err(Error::new(name, &opts, Some(&err_str), 2));
+               }
+           }
+       } else {
+           None
+       };
+
+       if matches.opt_present("no-read-after-write") && matches.opt_present("guard-audio-checksum") {
+           let err_str = "Cannot use 'no-read-after-write' and 'guard-audio-checksum' at the same time, since the audio is checked by reading the file back";
+           return Err(Error::new(name, &opts, Some(err_str), 1));
+       }
+
+       if matches.opt_present("dry-run") && matches.opt_present("repair") {
+           let err_str = "Cannot use 'dry-run' and 'repair' at the same time, since repairing a file rewrites it before its tag is read";
+           return Err(Error::new(name, &opts, Some(err_str), 1));
+       }
+
+       if matches.opt_present("json") {
+           for other in &["compact", "print-raw"] {
+               if matches.opt_present(other) {
+                   let err_str = format!("Cannot use 'json' and '{}' at the same time", other);
+                   return Err(Error::new(name, &opts, Some(&err_str), 1));
+               }
+           }
+       }
+
+       // Fields that weren't listed keep their usual order after the listed ones
+       if !matches.opt_present("omit-unlisted") {
+           let unlisted: Vec<Field> = PRINTOUT_FIELDS.iter().filter(|f| !field_order.contains(f)).copied().collect();
+           field_order.extend(unlisted);
+       }
+
+       let allowed_genres = match matches.opt_str("genre-validate") {
+           Some(file) => match fs::read_to_string(&file) {
+               Ok(contents) => Some(contents.lines()
+                   .map(str::trim)
+                   .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                   .map(|l| if matches.opt_present("genre-ignore-case") { l.to_lowercase() } else { l.to_string() })
+                   .collect()),
+               Err(_) => {
+                   let err_str = format!("Failed to read genre list {}", file);
+                   return Err(Error::new(name, &opts, Some(&err_str), 2));
+               }
+           },
+           None if matches.opt_present("genre-ignore-case") => {
+               let err_str = "'genre-ignore-case' only works along with 'genre-validate'";
+               return Err(Error::new(name, &opts, Some(err_str), 1));
+           }
+           None => None,
+       };
+
+       let field_json = match matches.opt_str("print-field-json") {
+           Some(s) => match str_to_field(&s) {
+               Some(f) => Some(f),
+               None => {
+                   let err_str = format!("Cannot print '{}' field because it does not exist!", &s);
+                   return Err(Error::new(name, &opts, Some(&err_str), 4));
+               }
+           },
+           None => None,
+       };
+
+       let version_of = match matches.opt_str("print-version-of") {
+           Some(s) => match str_to_field(&s) {
+               Some(f) => Some(f),
+               None => {
+                   let err_str = format!("Cannot look for '{}' field because it does not exist!", &s);
+                   return Err(Error::new(name, &opts, Some(&err_str), 4));
+               }
+           },
+           None => None,
+       };
+
+       let duplicates_by = if matches.opt_present("print-duplicates-by-tag") {
+           let mut fields: Vec<Field> = Vec::new();
+
+           for s in matches.opt_strs("duplicate-field") {
+               match str_to_field(&s) {
+                   Some(f) => fields.push(f),
+                   None => {
+                       let err_str = format!("Cannot group by '{}' field because it does not exist!", &s);
+                       return Err(Error::new(name, &opts, Some(&err_str), 4));
+                   }
+               }
+           }
+
+           if fields.is_empty() {
+               fields = vec![Field::Artist, Field::Title, Field::Album];
+           }
+
+           Some(fields)
+       } else {
+           None
+       };
+
+       let mut padding = Padding::default();
+
+       // The specific options come last so they override `--pad`
+       for opt in &["pad", "pad-track", "pad-disc"] {
+           if let Some(s) = matches.opt_str(opt) {
+               let width = match s.trim().parse::<usize>() {
+                   Ok(w) => w,
+                   Err(_) => {
+                       let err_str = format!("'{}' needs to be a positive integer", opt);
+                       return Err(Error::new(name, &opts, Some(&err_str), 3));
+                   }
+               };
+
+               match *opt {
+                   "pad" => {
+                       padding.track = width;
+                       padding.disc = width;
+                   }
+                   "pad-track" => padding.track = width,
+                   _ => padding.disc = width,
+               }
+           }
+       }
+
+       let watch = match matches.opt_str("watch") {
+           Some(_) if !matches.free.is_empty() => {
+               let err_str = "Cannot use 'watch' with files, it finds its own in the directory";
+               return Err(Error::new(name, &opts, Some(err_str), 1));
+           }
+           Some(_) if commands.is_empty() => {
+               let err_str = "'watch' needs field options to apply to the files it finds";
+               return Err(Error::new(name, &opts, Some(err_str), 1));
+           }
+           Some(dir) if !Path::new(&dir).is_dir() => {
+               let err_str = format!("{} isn't a directory that can be watched", dir);
+               return Err(Error::new(name, &opts, Some(&err_str), 2));
+           }
+           dir => dir,
+       };
+
+       #[cfg(feature = "analysis")]
+       let halt_timeout = match matches.opt_str("halt-timeout") {
+           Some(s) => match s.trim().parse::<f64>() {
+               Ok(secs) if secs > 0.0 && secs.is_finite() => Some(Duration::from_secs_f64(secs)),
+               _ => {
+                   let err_str = "'halt-timeout' needs to be a number of seconds above 0";
+                   return Err(Error::new(name, &opts, Some(err_str), 3));
+               }
+           },
+           None => None,
+       };
+
+       let tag_size_limit = match matches.opt_str("tag-size-limit") {
+           Some(s) => match s.trim().parse::<usize>() {
+               Ok(l) => Some(l),
+               Err(_) => {
+                   let err_str = "'tag-size-limit' needs to be a positive integer";
+                   return Err(Error::new(name, &opts, Some(err_str), 3));
+               }
+           },
+           None => None,
+       };
+
+       let id3_version = match matches.opt_str("id3-version").as_deref().map(str::trim) {
+           Some("2.3") => Some(id3::Version::Id3v23),
+           Some("2.4") => Some(id3::Version::Id3v24),
+           Some(s) => {
+               let err_str = format!("'id3-version' needs to be 2.3 or 2.4, got '{}'", s);
+               return Err(Error::new(name, &opts, Some(&err_str), 1));
+           }
+           None => None,
+       };
+
+       let mut skip = 0;
+       let mut limit = usize::MAX;
+
+       for opt in &["skip", "limit"] {
+           if let Some(s) = matches.opt_str(opt) {
+               let n = match s.trim().parse::<usize>() {
+                   Ok(n) => n,
+                   Err(_) => {
+                       let err_str = format!("'{}' needs to be a positive integer", opt);
+                       return Err(Error::new(name, &opts, Some(&err_str), 3));
+                   }
+               };
+
+               match *opt {
+                   "skip" => skip = n,
+                   _ => limit = n,
+               }
+           }
+       }
+
+       let jobs = match matches.opt_str("jobs").map(|s| s.trim().parse::<usize>()) {
+           Some(Ok(n)) if n > 0 => n,
+           Some(_) => {
+               let err_str = "'jobs' needs to be an integer above 0";
+               return Err(Error::new(name, &opts, Some(err_str), 3));
+           }
+           None if matches.opt_present("jobs") => default_jobs(),
+           None => 1,
+       };
+
+       let export_image_dir = matches.opt_str("export-image-all");
+       let dump_image = matches.opt_present("dump-image");
+
+       if let Some(dir) = &export_image_dir {
+           if !Path::new(dir).is_dir() {
+               let err_str = format!("Directory {} does not exist, or we may not have valid permissions", dir);
+               return Err(Error::new(name, &opts, Some(&err_str), 2));
+           }
+       }
+
+       let mut files = free;
+       let mut file_commands: HashMap<String, Vec<Command>> = HashMap::new();
+
+       if let Some(manifest) = matches.opt_str("fields-from") {
+           for (f, c) in read_fields_file(&manifest, years, name, &opts)? {
+               if !files.contains(&f) {
+                   files.push(f.clone());
+               }
+
+               file_commands.insert(f, c);
+           }
+       }
+
+       let files: Vec<String> = files.into_iter().skip(skip).take(limit).collect();
+
+       // Checked once the presets' commands are in too, against the files left after --skip and --limit
+       if matches.opt_present("album-only") && files.len() > 1 {
+           for c in &commands {
+               if let Command::Set(f, _) = c {
+                   if !is_album_field(f) {
+                       let err_str = format!("Cannot set '{}' on {} files with --album-only, since each track has its own", field_to_str(f), files.len());
+                       return Err(Error::new(name, &opts, Some(&err_str), 1));
+                   }
+               }
+           }
+       }
+
+       Ok(Config {
+           properties: matches.opt_present("properties"),
+           keep_going: matches.opt_present("keep-going") || matches.opt_present("continue-on-error"),
+           validate_image: matches.opt_present("validate-image"),
+           art_info: matches.opt_present("print-art-info"),
+           modified_only: matches.opt_present("print-modified-only"),
+           print_path: matches.opt_present("print-path"),
+           no_default_print: matches.opt_present("no-default-print"),
+           quiet: matches.opt_present("quiet"),
+           strict: matches.opt_present("strict"),
+           export_image_dir,
+           dump_image,
+           tag_size_limit,
+           require_image: matches.opt_present("require-image"),
+           id3_version,
+           confirm: matches.opt_present("confirm"),
+           yes: matches.opt_present("yes"),
+           export_cue: matches.opt_present("export-cue"),
+           stats: matches.opt_present("stats"),
+           repair: matches.opt_present("repair"),
+           compact: matches.opt_present("compact"),
+           json: matches.opt_present("json"),
+           transaction: matches.opt_present("transaction"),
+           dry_run: matches.opt_present("dry-run"),
+           json_objects: Mutex::new(Vec::new()),
+           jobs,
+           all_tags_json: matches.opt_present("all-tags-json"),
+           field_presence: matches.opt_present("print-field-presence"),
+           print_encoding: matches.opt_present("print-encoding"),
+           print_hash: matches.opt_present("print-hash"),
+           guard_audio: matches.opt_present("guard-audio-checksum"),
+           no_read_after_write: matches.opt_present("no-read-after-write"),
+           relative_to,
+           roundtrip: matches.opt_present("roundtrip-test"),
+           bom_issues: matches.opt_present("print-bom-issues"),
+           allowed_genres,
+           genre_ignore_case: matches.opt_present("genre-ignore-case"),
+           changed_fields: matches.opt_present("print-changed-fields"),
+           sidecar: if matches.opt_present("write-cover-to-sidecar") {
+               Some(matches.opt_str("sidecar-name").unwrap_or_default())
+           } else {
+               None
+           },
+           force: matches.opt_present("force"),
+           tag_count: matches.opt_present("print-tag-count"),
+           clear_related: matches.opt_present("clear-related"),
+           summary_json: matches.opt_present("summary-json"),
+           field_json,
+           duplicates_by,
+           version_of,
+           csv: matches.opt_present("csv"),
+           #[cfg(feature = "analysis")]
+           replaygain: matches.opt_present("apply-replaygain"),
+           #[cfg(feature = "analysis")]
+           halt_timeout,
+           watch,
+           files,
+           commands,
+           file_commands,
+           only,
+           field_order,
+           padding,
+           warnings: Mutex::new(Box::new(io::stderr())),
+           opts,
+           name: name.to_string(),
+       })
+    }
+
+    /// The main part of the program that does the metadata modifications, printing to stdout
+    pub fn exec(self) -> Result<(), Error> {
+        self.exec_to(&mut io::stdout())
+    }
+
+    /// Does the same as `exec`, but writes everything it would print to stdout to `out` instead.
+    /// Warnings and prompts go to stderr, unless `warnings_to` sends them elsewhere.
+    pub fn exec_to(mut self, out: &mut impl Write) -> Result<(), Error> {
+        let out: &mut dyn Write = out;
+
+        if self.export_cue {
+            return self.print_cue(out);
+        }
+
+        if self.stats {
+            return self.print_stats(out);
+        }
+
+        if self.field_presence {
+            return self.print_field_presence(out);
+        }
+
+        if let Some(fields) = &self.duplicates_by {
+            return self.print_duplicates(fields, out);
+        }
+
+        if self.roundtrip {
+            return self.roundtrip_test(out);
+        }
+
+        if self.bom_issues {
+            return self.print_bom_issues(out);
+        }
+
+        if let Some(allowed) = &self.allowed_genres {
+            return self.validate_genres(allowed, out);
+        }
+
+        if let Some(field) = &self.version_of {
+            return self.print_version_of(field, out);
+        }
+
+        #[cfg(feature = "analysis")]
+        if self.replaygain {
+            return self.apply_replaygain(out);
+        }
+
+        if let Some(dir) = self.watch.clone() {
+            self.read_stdin()?;
+            return self.watch(&dir, out);
+        }
+
+        if self.confirm {
+            let stdin = io::stdin();
+            let mut lock = stdin.lock();
+            let terminal: Option<&mut dyn BufRead> = if stdin.is_terminal() { Some(&mut lock) } else { None };
+            self.ask_to_confirm(terminal)?;
+        }
+
+        self.read_stdin()?;
+
+        // With --transaction every file is edited as a copy, and the copies are only moved over the
+        // originals once all of them succeed. Dropping the copies puts everything back.
+        let staged = if self.transaction { self.stage_files()? } else { Vec::new() };
+
+        let mut failures: Vec<(&str, Error)> = Vec::new();
+        let mut modified: Vec<usize> = Vec::new();
+
+        // With --jobs the files are all worked on up front, and their results are gone through in
+        // order below just like when they're done one at a time
+        let mut results = if self.jobs > 1 { self.exec_parallel(&staged) } else { Vec::new() };
+
+        for (i, f) in self.files.iter().enumerate() {
+            let path = staged.get(i).map_or(f.as_str(), |t| t.path.as_str());
+
+            let result = match results.get_mut(i).and_then(Option::take) {
+                Some((result, output)) => {
+                    if out.write_all(&output).is_err() {
+                        let error_str = "Error when trying to print to stdout";
+                        return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                    }
+                    result
+                }
+                None => self.exec_file(f, path, out),
+            };
+
+            match result {
+                Ok(true) => modified.push(i),
+                Ok(false) => {}
+                Err(e) => {
+                    if !self.keep_going {
+                        let mut names = vec![self.shown(f)];
+                        let mut errors = vec![e];
+                        let mut processed = i + 1;
+
+                        // With --jobs, the files that were already being worked on were still
+                        // finished, so they're reported like any other
+                        for (j, result) in results.iter_mut().enumerate().skip(i + 1) {
+                            let (result, output) = match result.take() {
+                                Some(r) => r,
+                                None => continue,
+                            };
+
+                            if out.write_all(&output).is_err() {
+                                let error_str = "Error when trying to print to stdout";
+                                return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                            }
+
+                            processed += 1;
+                            match result {
+                                Ok(true) => modified.push(j),
+                                Ok(false) => {}
+                                Err(e) => {
+                                    self.warn(&e.error_str);
+                                    names.push(self.shown(&self.files[j]));
+                                    errors.push(e);
+                                }
+                            }
+                        }
+
+                        if self.transaction {
+                            self.warn(&format!("No files were changed, since `{}` failed", f));
+                            modified.clear();
+                        }
+
+                        if self.json {
+                            self.print_json_objects(out)?;
+                        }
+
+                        if self.summary_json {
+                            let failed: Vec<(&str, &Error)> = names.iter().map(String::as_str).zip(&errors).collect();
+                            self.emit(out, &summary_json(processed, modified.len(), self.files.len() - processed, &failed))?;
+                        }
+
+                        return Err(errors.remove(0));
+                    }
+
+                    self.warn(&e.error_str);
+                    failures.push((f, e));
+                }
+            }
+        }
+
+        if self.transaction {
+            if failures.is_empty() {
+                self.commit_staged(staged, &modified)?;
+            } else {
+                self.warn(&format!("No files were changed, since {} of them failed", failures.len()));
+                modified.clear();
+            }
+        }
+
+        if self.json {
+            self.print_json_objects(out)?;
+        }
+
+        if self.summary_json {
+            let names: Vec<String> = failures.iter().map(|(f, _)| self.shown(f)).collect();
+            let errors: Vec<(&str, &Error)> = names.iter().zip(&failures).map(|(n, (_, e))| (n.as_str(), e)).collect();
+            self.emit(out, &summary_json(self.files.len(), modified.len(), 0, &errors))?;
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else if failures.len() == self.files.len() {
+            let error_str = format!("All {} files failed", failures.len());
+            Err(Error::new(&self.name, &self.opts, Some(&error_str), failures[0].1.error_code))
+        } else {
+            let error_str = format!("{} of {} files failed", failures.len(), self.files.len());
+            Err(Error::new(&self.name, &self.opts, Some(&error_str), 8))
+        }
+    }
+
+    /// Runs every file on `self.jobs` threads for `--jobs`, keeping what each one printed so it can
+    /// be shown in order. Without `--keep-going`, files stop being started once one fails, so the
+    /// files after it can be left without a result.
+    fn exec_parallel(&self, staged: &[TempFile]) -> Vec<Option<JobResult>> {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results = Mutex::new((0..self.files.len()).map(|_| None).collect::<Vec<_>>());
+
+        thread::scope(|scope| {
+            for _ in 0..cmp::min(self.jobs, self.files.len()) {
+                scope.spawn(|| loop {
+                    if failed.load(Ordering::SeqCst) && !self.keep_going {
+                        break;
+                    }
+
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let f = match self.files.get(i) {
+                        Some(f) => f,
+                        None => break,
+                    };
+                    let path = staged.get(i).map_or(f.as_str(), |t| t.path.as_str());
+
+                    let mut output = Vec::new();
+                    let result = self.exec_file(f, path, &mut output);
+
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+
+                    results.lock().unwrap()[i] = Some((result, output));
+                });
+            }
+        });
+
+        results.into_inner().unwrap()
+    }
+
+    /// Copies every file for `--transaction`, in the order of `self.files`
+    fn stage_files(&self) -> Result<Vec<TempFile>, Error> {
+        let mut staged = Vec::new();
+
+        for f in &self.files {
+            match TempFile::beside(f) {
+                Ok(t) => staged.push(t),
+                Err(_) => {
+                    let err_str = format!("Failed to copy `{}` to edit it as part of the transaction", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 2));
+                }
+            }
+        }
+
+        Ok(staged)
+    }
+
+    /// Moves the copies of the files at the indices in `modified` over their originals. The copies
+    /// of files that weren't changed are deleted.
+    fn commit_staged(&self, staged: Vec<TempFile>, modified: &[usize]) -> Result<(), Error> {
+        for (i, temp) in staged.into_iter().enumerate() {
+            if modified.contains(&i) && temp.persist(&self.files[i]).is_err() {
+                let err_str = format!("Failed to move the edited copy of `{}` over it. The files before it were already changed", self.files[i]);
+                return Err(Error::new(&self.name, &self.opts, Some(&err_str), 2));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints a CUE sheet with a track for each file. The album's title and performer come from the
+    /// first file, and tracks without a track number are numbered by their position.
+    fn print_cue(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let mut cue = String::new();
+
+        for (i, f) in self.files.iter().enumerate() {
+            let tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            if i == 0 {
+                if let Some(p) = tag.album_artist_str().or_else(|| tag.artist_str()) {
+                    cue.push_str(&format!("PERFORMER {}\n", cue_quote(p)));
+                }
+                if let Some(t) = tag.album_title() {
+                    cue.push_str(&format!("TITLE {}\n", cue_quote(t)));
+                }
+            }
+
+            let name = Path::new(f).file_name().unwrap_or_default().to_string_lossy();
+            let track = tag.track_number().unwrap_or(i as u32 + 1);
+
+            cue.push_str(&format!("FILE {} {}\n", cue_quote(&name), cue_file_type(f)));
+            cue.push_str(&format!("  TRACK {:02} AUDIO\n", track));
+            if let Some(t) = tag.title() {
+                cue.push_str(&format!("    TITLE {}\n", cue_quote(t)));
+            }
+            if let Some(a) = tag.artist_str() {
+                cue.push_str(&format!("    PERFORMER {}\n", cue_quote(a)));
+            }
+            cue.push_str("    INDEX 01 00:00:00\n");
+        }
+
+        if out.write_all(cue.as_bytes()).is_err() {
+            let error_str = "Error when trying to print to stdout";
+            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+        }
+
+        Ok(())
+    }
+
+    /// Prints statistics over every file. Files without a known length are left out of the average.
+    fn print_stats(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let mut size: u64 = 0;
+        let mut missing_image = 0;
+        let mut albums: HashSet<String> = HashSet::new();
+        let mut artists: HashSet<String> = HashSet::new();
+        let mut total_length = Duration::default();
+        let mut timed = 0;
+
+        for f in &self.files {
+            let tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            // A file whose properties can't be read just isn't counted in the average length
+            let properties = properties::read(f).unwrap_or_default();
+
+            size += fs::metadata(f).map(|m| m.len()).unwrap_or(0);
+
+            if tag.album_cover().is_none() {
+                missing_image += 1;
+            }
+            if let Some(a) = tag.album_title() {
+                albums.insert(a.to_string());
+            }
+            if let Some(a) = tag.artist_str() {
+                artists.insert(a.to_string());
+            }
+            if let Some(d) = properties.duration {
+                total_length += d;
+                timed += 1;
+            }
+        }
+
+        let average = if timed > 0 { Some(total_length / timed) } else { None };
+
+        if self.json {
+            let average = average.map(|d| format!("{:.3}", d.as_secs_f64())).unwrap_or_else(|| "null".to_string());
+
+            return self.emit(out, &format!(
+                "{{\"files\":{},\"total_size\":{},\"missing_image\":{},\"albums\":{},\"artists\":{},\"average_length\":{}}}",
+                self.files.len(), size, missing_image, albums.len(), artists.len(), average,
+            ));
+        }
+
+        self.emit(out, &format!("Files: {}", self.files.len()))?;
+        self.emit(out, &format!("Total Size: {} bytes", size))?;
+        self.emit(out, &format!("Missing Image: {}", missing_image))?;
+        self.emit(out, &format!("Albums: {}", albums.len()))?;
+        self.emit(out, &format!("Artists: {}", artists.len()))?;
+        self.emit(out, &format!("Average Length: {}", format_length(average)))?;
+
+        Ok(())
+    }
+
+    /// Prints a row for each file marking which of the printout fields it has, under a header of
+    /// the field names
+    fn print_field_presence(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let names: Vec<&str> = PRINTOUT_FIELDS.iter().map(field_to_str).collect();
+
+        if self.csv {
+            self.emit(out, &format!("file,{}", names.join(",")))?;
+        } else {
+            self.emit(out, &format!("{}  file", names.join("  ")))?;
+        }
+
+        for f in &self.files {
+            let mut tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            let marks: Vec<&str> = PRINTOUT_FIELDS.iter()
+                .map(|field| if field_present(&mut *tag, field) { "Y" } else { "N" })
+                .collect();
+
+            if self.csv {
+                self.emit(out, &format!("{},{}", csv_field(&self.shown(f)), marks.join(",")))?;
+            } else {
+                let row: Vec<String> = marks.iter().zip(&names).map(|(m, n)| format!("{:width$}", m, width = n.len())).collect();
+                self.emit(out, &format!("{}  {}", row.join("  "), self.shown(f)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the tag of a copy of each file back unchanged, reads it again, and reports any field
+    /// or frame that didn't come back the same
+    fn roundtrip_test(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let mut lossy = 0;
+
+        for f in &self.files {
+            let temp = match fs::read(f).and_then(|d| TempFile::new(&d)) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failed to copy `{}` to the temporary directory", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 2));
+                }
+            };
+
+            let read = || match inner::read(&temp.path) {
+                Ok(t) => Ok(t),
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    Err(Error::new(&self.name, &self.opts, Some(&err_str), 7))
+                }
+            };
+
+            let mut tag = read()?;
+            let before = roundtrip_values(&mut *tag, &temp.path);
+
+            if tag.write_to_path(&temp.path).is_err() {
+                let err_str = format!("Failed to write the tag of `{}` back", f);
+                return Err(Error::new(&self.name, &self.opts, Some(&err_str), 2));
+            }
+
+            let after = roundtrip_values(&mut *read()?, &temp.path);
+
+            let lost: Vec<&(String, String)> = before.iter().filter(|v| !after.contains(v)).collect();
+            let gained: Vec<&(String, String)> = after.iter().filter(|v| !before.contains(v)).collect();
+
+            if lost.is_empty() && gained.is_empty() {
+                self.emit(out, &format!("{}: OK", self.shown(f)))?;
+                continue;
+            }
+
+            lossy += 1;
+            self.emit(out, &format!("{}: changed by a round trip", self.shown(f)))?;
+
+            for (key, _) in lost {
+                match gained.iter().find(|(k, _)| k == key) {
+                    Some(_) => self.emit(out, &format!("  {}: changed", key))?,
+                    None => self.emit(out, &format!("  {}: lost", key))?,
+                }
+            }
+
+            for (key, _) in gained {
+                if !before.iter().any(|(k, _)| k == key) {
+                    self.emit(out, &format!("  {}: added", key))?;
+                }
+            }
+        }
+
+        if lossy > 0 {
+            let err_str = format!("{} of {} files didn't survive a round trip unchanged", lossy, self.files.len());
+            return Err(Error::new(&self.name, &self.opts, Some(&err_str), 9));
+        }
+
+        Ok(())
+    }
+
+    /// Prints each text field that has stray characters in it, along with the kinds it has
+    fn print_bom_issues(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let mut affected = 0;
+
+        for f in &self.files {
+            let mut tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            let mut found = false;
+
+            for field in &TEXT_FIELDS {
+                let kinds = stray_kinds(&field_value(&mut *tag, field, &Padding::default()));
+
+                if !kinds.is_empty() {
+                    self.emit(out, &format!("{}: {}: {}", self.shown(f), field_to_str(field), kinds.join(", ")))?;
+                    found = true;
+                }
+            }
+
+            if found {
+                affected += 1;
+            }
+        }
+
+        if affected > 0 {
+            let err_str = format!("{} of {} files have stray characters in their tags, which `--strip-control-chars` removes", affected, self.files.len());
+            return Err(Error::new(&self.name, &self.opts, Some(&err_str), 9));
+        }
+
+        Ok(())
+    }
+
+    /// Prints each file with a genre that isn't in `allowed`. Files without a genre are left out.
+    fn validate_genres(&self, allowed: &[String], out: &mut dyn Write) -> Result<(), Error> {
+        let mut invalid = 0;
+
+        for f in &self.files {
+            let mut tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            let genre = match inner::genre(&mut *tag) {
+                Ok(Some(g)) => g,
+                Ok(None) => continue,
+                Err(_) => {
+                    self.warn(&format!("Warning: genres can't be read from `{}`'s tag format, skipping it", f));
+                    continue;
+                }
+            };
+
+            let key = if self.genre_ignore_case { genre.to_lowercase() } else { genre.clone() };
+
+            if !allowed.contains(&key) {
+                self.emit(out, &format!("{}: {}", self.shown(f), genre))?;
+                invalid += 1;
+            }
+        }
+
+        if invalid > 0 {
+            let err_str = format!("{} of {} files have a genre that isn't allowed", invalid, self.files.len());
+            return Err(Error::new(&self.name, &self.opts, Some(&err_str), 9));
+        }
+
+        Ok(())
+    }
+
+    /// Prints the value of `field` that's shown for each file, then its value in each of the file's
+    /// tag containers
+    fn print_version_of(&self, field: &Field, out: &mut dyn Write) -> Result<(), Error> {
+        for f in &self.files {
+            let mut tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            let values = containers::values(Path::new(f), field_to_str(field));
+
+            self.emit(out, &format!("{}: {}", self.shown(f), field_to_str(field)))?;
+            self.emit(out, &format!("  shown: {}", field_value(&mut *tag, field, &Padding::default())))?;
+
+            for (container, value) in &values {
+                self.emit(out, &format!("  {}: {}", container, value.as_deref().unwrap_or("(not set)")))?;
+            }
+
+            if values.is_empty() {
+                self.emit(out, "  no ID3v2, ID3v1, or APEv2 tag holds this field")?;
+            } else if containers::disagree(&values) {
+                self.emit(out, "  the tags disagree")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints each group of files that share the same values for `fields`, in the order the groups
+    /// were first seen. Values are compared ignoring case and surrounding whitespace, and files
+    /// with none of the fields are left out.
+    fn print_duplicates(&self, fields: &[Field], out: &mut dyn Write) -> Result<(), Error> {
+        // The values each group is shown with, which are those of its first file
+        let mut groups: Vec<(Vec<String>, Vec<&str>)> = Vec::new();
+        let mut keys: Vec<Vec<String>> = Vec::new();
+
+        for f in &self.files {
+            let mut tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for reading", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            if !fields.iter().any(|field| field_present(&mut *tag, field)) {
+                continue;
+            }
+
+            let values: Vec<String> = fields.iter().map(|field| field_value(&mut *tag, field, &Padding::default())).collect();
+            let key: Vec<String> = values.iter().map(|v| v.trim().to_lowercase()).collect();
+
+            match keys.iter().position(|k| *k == key) {
+                Some(i) => groups[i].1.push(f),
+                None => {
+                    keys.push(key);
+                    groups.push((values, vec![f]));
+                }
+            }
+        }
+
+        let duplicates: Vec<&(Vec<String>, Vec<&str>)> = groups.iter().filter(|(_, files)| files.len() > 1).collect();
+
+        if duplicates.is_empty() {
+            self.warn("No duplicates found");
+        }
+
+        for (i, (values, files)) in duplicates.iter().enumerate() {
+            if i > 0 {
+                self.emit(out, "")?;
+            }
+
+            let labels: Vec<String> = fields.iter().zip(values).map(|(field, v)| format!("{}: {}", field_label(field), v)).collect();
+            self.emit(out, &labels.join(", "))?;
+
+            for f in files {
+                self.emit(out, &format!("  {}", self.shown(f)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Polls `dir` for audio files that weren't there when it started, and runs the commands on each
+    /// one once it's settled, as `poll_once` does. Each file is edited as a copy that's moved over
+    /// it, so being interrupted never leaves a file half written. Never returns unless `dir` can't
+    /// be read.
+    fn watch(&self, dir: &str, out: &mut dyn Write) -> Result<(), Error> {
+        const INTERVAL: Duration = Duration::from_secs(1);
+
+        let mut seen: HashSet<PathBuf> = self.watched_files(dir)?.into_iter().collect();
+        let mut pending = HashMap::new();
+
+        self.warn(&format!("Watching {} for new files", dir));
+
+        loop {
+            thread::sleep(INTERVAL);
+            self.poll_once(dir, &mut seen, &mut pending, out)?;
+        }
+    }
+
+    /// The audio files directly in the directory `--watch` watches
+    fn watched_files(&self, dir: &str) -> Result<Vec<PathBuf>, Error> {
+        match fs::read_dir(dir) {
+            Ok(entries) => Ok(entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file() && is_audio(p)).collect()),
+            Err(_) => {
+                let err_str = format!("Failed to read the directory {}", dir);
+                Err(Error::new(&self.name, &self.opts, Some(&err_str), 2))
+            }
+        }
+    }
+
+    /// A single poll of `--watch`. Files that aren't in `seen` are new, and `pending` holds the size
+    /// and modification time each new file had when it was last polled. A file is only edited once
+    /// those stay the same between two polls, so files that are still being copied in are left
+    /// alone. Returns the files that were edited.
+    fn poll_once(&self, dir: &str, seen: &mut HashSet<PathBuf>, pending: &mut HashMap<PathBuf, (u64, Option<SystemTime>)>, out: &mut dyn Write) -> Result<Vec<String>, Error> {
+        let mut edited = Vec::new();
+
+        for path in self.watched_files(dir)? {
+            if seen.contains(&path) {
+                continue;
+            }
+
+            let state = match fs::metadata(&path) {
+                Ok(m) => (m.len(), m.modified().ok()),
+                Err(_) => continue,
+            };
+
+            if pending.get(&path) != Some(&state) {
+                pending.insert(path, state);
+                continue;
+            }
+
+            pending.remove(&path);
+            seen.insert(path.clone());
+
+            let f = path.to_string_lossy().into_owned();
+            let result = TempFile::beside(&f)
+                .map_err(|_| Error::new(&self.name, &self.opts, Some(&format!("Failed to copy `{}` to edit it", f)), 2))
+                .and_then(|temp| match self.exec_file(&f, &temp.path, out)? {
+                    true => temp.persist(&f).map_err(|_| Error::new(&self.name, &self.opts, Some(&format!("Failed to move the edited copy of `{}` over it", f)), 2)),
+                    false => Ok(()),
+                });
+
+            match result {
+                Ok(()) => edited.push(f),
+                Err(e) => self.warn(&e.error_str),
+            }
+        }
+
+        // Files that were removed before they settled
+        pending.retain(|p, _| p.exists());
+
+        Ok(edited)
+    }
+
+    /// Works out the ReplayGain values of every file and writes them, reporting each one. The album
+    /// values treat all of the files as one album.
+    #[cfg(feature = "analysis")]
+    fn apply_replaygain(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let mut analyzed: Vec<&String> = Vec::new();
+        let mut tracks = Vec::new();
+        let mut failures: Vec<Error> = Vec::new();
+
+        for f in &self.files {
+            match self.analyze(f, analysis::analyze) {
+                Ok(l) => {
+                    analyzed.push(f);
+                    tracks.push(l);
+                }
+                Err(e) => {
+                    if !self.keep_going {
+                        return Err(e);
+                    }
+
+                    self.warn(&e.error_str);
+                    failures.push(e);
+                }
+            }
+        }
+
+        if tracks.is_empty() {
+            let error_str = format!("All {} files failed", failures.len());
+            return Err(Error::new(&self.name, &self.opts, Some(&error_str), failures[0].error_code));
+        }
+
+        let album = analysis::Loudness::album(&tracks);
+        let format_gain = |l: &analysis::Loudness| match l.gain() {
+            Some(g) => format!("{:.2} dB", g),
+            None => "0.00 dB".to_string(),
+        };
+
+        for (f, track) in analyzed.into_iter().zip(&tracks) {
+            let mut tag = match inner::read(f) {
+                Ok(t) => t,
+                Err(_) => {
+                    let err_str = format!("Failure to open `{}` for editing", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            let values = [
+                ("REPLAYGAIN_TRACK_GAIN", format_gain(track)),
+                ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", track.peak)),
+                ("REPLAYGAIN_ALBUM_GAIN", format_gain(&album)),
+                ("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album.peak)),
+            ];
+
+            for (key, value) in &values {
+                if inner::set_user_text(&mut *tag, key, value).is_err() {
+                    let err_str = format!("ReplayGain values can't be written to `{}`'s tag format", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            }
+
+            if !self.dry_run && tag.write_to_path(f).is_err() {
+                let error_str = format!("Failed to write new tags to {}", f);
+                return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+            }
+
+            let verb = self.verb("", "would get ");
+            self.emit(out, &format!("{}: {}track gain {}, peak {:.6}", self.shown(f), verb, format_gain(track), track.peak))?;
+        }
+
+        self.emit(out, &format!("Album: gain {}, peak {:.6}", format_gain(&album), album.peak))?;
+
+        if !failures.is_empty() {
+            let error_str = format!("{} of {} files failed", failures.len(), self.files.len());
+            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 8));
+        }
+
+        Ok(())
+    }
+
+    /// Measures the loudness of `f` with `analyze`, which is `analysis::analyze` outside of tests.
+    /// With `--halt-timeout`, the analysis runs on its own thread and is given up on once the time
+    /// runs out. The thread can't be stopped, so it's left to finish in the background.
+    #[cfg(feature = "analysis")]
+    fn analyze(&self, f: &str, analyze: fn(&str) -> Option<analysis::Loudness>) -> Result<analysis::Loudness, Error> {
+        let loudness = match self.halt_timeout {
+            Some(timeout) => {
+                let (sender, receiver) = mpsc::channel();
+                let path = f.to_string();
+
+                thread::spawn(move || {
+                    let _ = sender.send(analyze(&path));
+                });
+
+                match receiver.recv_timeout(timeout) {
+                    Ok(l) => l,
+                    Err(_) => {
+                        let err_str = format!("Analyzing `{}` took longer than {} seconds, giving up on it", f, timeout.as_secs_f64());
+                        return Err(Error::new(&self.name, &self.opts, Some(&err_str), 11));
+                    }
+                }
+            }
+            None => analyze(f),
+        };
+
+        match loudness {
+            Some(l) => Ok(l),
+            None => {
+                let err_str = format!("`{}` couldn't be analyzed, only mono and stereo WAV files can be", f);
+                Err(Error::new(&self.name, &self.opts, Some(&err_str), 7))
+            }
+        }
+    }
+
+    /// Runs every command against a music file held in memory, returning the modified file.
+    /// `name` is the file's name, which is what messages show, and what options like
+    /// `--normalize-track-from-filename` and `--parse-from-directory-structure` work from.
+    /// Everything that would be printed is written to `out`, like `exec_to`.
+    ///
+    /// lofty only reads and writes tags through paths, so the data is staged in a temporary file
+    /// for the duration of the call.
+    pub fn exec_bytes(&mut self, name: &str, data: &[u8], out: &mut impl Write) -> Result<Vec<u8>, Error> {
+        let out: &mut dyn Write = out;
+
+        self.read_stdin()?;
+
+        let temp = match TempFile::new(data) {
+            Ok(t) => t,
+            Err(_) => {
+                let error_str = "Failed to stage the file in the temporary directory";
+                return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+            }
+        };
+
+        self.exec_file(name, &temp.path, out)?;
+
+        if self.json {
+            self.print_json_objects(out)?;
+        }
+
+        match fs::read(&temp.path) {
+            Ok(data) => Ok(data),
+            Err(_) => {
+                let error_str = "Failed to read back the modified file";
+                Err(Error::new(&self.name, &self.opts, Some(error_str), 2))
+            }
+        }
+    }
+
+    /// Describes the changes about to be made and asks whether to go ahead, reading the answer from
+    /// `terminal`. Without a terminal to ask on, only `--yes` lets the changes through.
+    fn ask_to_confirm(&self, terminal: Option<&mut dyn BufRead>) -> Result<(), Error> {
+        let mut changes: Vec<String> = Vec::new();
+
+        for c in self.commands.iter().chain(self.file_commands.values().flatten()) {
+            if let Some(change) = describe_change(c) {
+                if !changes.contains(&change) {
+                    changes.push(change);
+                }
+            }
+        }
+
+        if changes.is_empty() || self.yes {
+            return Ok(());
+        }
+
+        self.warn(&format!("About to {} in {} file(s)", changes.join(", "), self.files.len()));
+
+        let terminal = match terminal {
+            Some(t) => t,
+            None => {
+                let error_str = "Not going ahead without confirmation. Use --yes to confirm when stdin isn't a terminal.";
+                return Err(Error::new(&self.name, &self.opts, Some(error_str), 10));
+            }
+        };
+
+        {
+            let mut warnings = self.warnings.lock().unwrap();
+            let _ = write!(warnings, "Go ahead? [y/N] ");
+            let _ = warnings.flush();
+        }
+
+        let mut answer = String::new();
+        if terminal.read_line(&mut answer).is_err() {
+            let error_str = "Issue when reading stdin.";
+            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+        }
+
+        match answer.trim() {
+            "y" | "Y" | "yes" => Ok(()),
+            _ => {
+                let error_str = "Not going ahead, nothing was changed.";
+                Err(Error::new(&self.name, &self.opts, Some(error_str), 10))
+            }
+        }
+    }
+
+    /// Reads stdin into memory for the commands that take their data from it. Stdin can only be
+    /// read once, so the buffer is reused for every file.
+    fn read_stdin(&mut self) -> Result<(), Error> {
+        if !self.commands.iter().any(|c| matches!(c, Command::Set(_, Data::StdIn))) {
+            return Ok(());
+        }
+
+        let mut stdin = io::stdin();
+
+        if stdin.is_terminal() {
+            let error_str = "Refusing to read from stdin because it is a terminal. Pipe the data in instead.";
+            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+        if stdin.read_to_end(&mut buf).is_err() {
+            let error_str = "Issue when reading stdin.";
+            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+        }
+
+        self.fill_stdin(buf);
+
+        Ok(())
+    }
+
+    /// Gives what was read from stdin to the command that takes its data from it
+    fn fill_stdin(&mut self, buf: Vec<u8>) {
+        for c in &mut self.commands {
+            if let Command::Set(field, d @ Data::StdIn) = c {
+                *d = match field {
+                    Field::Image => Data::Bytes(buf),
+                    _ => Data::Str(String::from_utf8_lossy(&buf).trim_end_matches(['\n', '\r']).to_string()),
+                };
+
+                // Only one command can read from stdin
+                break;
+            }
+        }
+    }
+
+    /// How a change is described: `done` once it's been made, or `would` when `--dry-run` only
+    /// reports it
+    fn verb<'a>(&self, done: &'a str, would: &'a str) -> &'a str {
+        if self.dry_run { would } else { done }
+    }
+
+    /// Writes a warning, or a message about what's being done, to wherever `warnings_to` sends them.
+    /// One that can't be written isn't worth failing over.
+    fn warn(&self, line: &str) {
+        let _ = writeln!(self.warnings.lock().unwrap(), "{}", line);
+    }
+
+    /// Writes a line of output to `out`
+    fn emit(&self, out: &mut dyn Write, line: &str) -> Result<(), Error> {
+        match writeln!(out, "{}", line) {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                let error_str = "Error when trying to print to stdout";
+                Err(Error::new(&self.name, &self.opts, Some(error_str), 2))
+            }
+        }
+    }
+
+    /// How `f` is shown in output, relative to the `--print-relative` directory when one is given
+    fn shown(&self, f: &str) -> String {
+        match &self.relative_to {
+            Some(base) => {
+                let path = fs::canonicalize(f).unwrap_or_else(|_| PathBuf::from(f));
+                relative_path(&path, base).to_string_lossy().into_owned()
+            }
+            None => f.to_string(),
+        }
+    }
+
+    /// The commands to run on `f`. Its own commands from `--fields-from` take priority over the
+    /// ones given for every file.
+    fn commands_for(&self, f: &str) -> Vec<&Command> {
+        let own = self.file_commands.get(f).map(Vec::as_slice).unwrap_or_default();
+
+        self.commands.iter()
+            .filter(|c| !own.iter().any(|o| overlaps(o, c)))
+            .chain(own)
+            .collect()
+    }
+
+    /// Whether a read mode was picked that replaces the default printout, or it was turned off
+    fn replaces_printout(&self) -> bool {
+        self.no_default_print || self.all_tags_json || self.field_json.is_some() || self.tag_count || self.print_encoding || self.print_hash || self.art_info || self.properties || self.export_image_dir.is_some() || self.dump_image || self.modified_only || self.changed_fields
+    }
+
+    /// The printout shown when nothing else was printed
+    fn default_printout(&self, f: &str, tag: &mut dyn AudioTag, properties: &Properties) -> String {
+        if self.compact {
+            printout_compact(&self.shown(f), tag, &self.only, &self.padding)
+        } else {
+            printout(tag, properties, &self.only, &self.field_order, &self.padding)
+        }
+    }
+
+    /// The error for a `Set` command whose data is the wrong kind for its field. Parsing and
+    /// `Config::from_commands` never make one, but `apply_command` can be given one.
+    fn wrong_data(&self, f: &str, field: &Field) -> Error {
+        let error_str = format!("Couldn't set {} on `{}`: it was given the wrong kind of value", field_to_str(field), f);
+        Error::new(&self.name, &self.opts, Some(&error_str), 7)
+    }
+
+    /// Prints the default printout, or holds onto it to be printed with the others for `--json`
+    fn show_default_printout(&self, f: &str, tag: &mut dyn AudioTag, properties: &Properties, out: &mut dyn Write) -> Result<(), Error> {
+        if self.json {
+            self.json_objects.lock().unwrap().push((f.to_string(), printout_json(&self.shown(f), tag, properties, &self.only)));
+        } else {
+            self.emit(out, &self.default_printout(f, tag, properties))?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints the objects held onto for `--json`: on their own for a single file, and as an array
+    /// otherwise
+    fn print_json_objects(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let mut objects = self.json_objects.lock().unwrap();
+        objects.sort_by_key(|(f, _)| self.files.iter().position(|e| e == f));
+        let objects: Vec<&str> = objects.iter().map(|(_, o)| o.as_str()).collect();
+
+        // No files at all means a single file held in memory
+        if self.files.len() <= 1 && objects.len() == 1 {
+            self.emit(out, objects[0])?;
+        } else if objects.is_empty() {
+            self.emit(out, "[]")?;
+        } else {
+            self.emit(out, &format!("[\n  {}\n]", objects.join(",\n  ")))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a file back from `path` after it's been written, and warns about any fields that were
+    /// set but didn't make it into the file. This happens when the file's tag format can't hold a
+    /// field. The file is only read again when a command set a field, and `--no-read-after-write`
+    /// skips it altogether.
+    fn warn_dropped_fields(&self, f: &str, path: &str, written: &mut dyn AudioTag) {
+        let fields: Vec<&Field> = self.commands_for(f).into_iter().filter_map(|c| match c {
+            Command::Set(f, _) => Some(f),
+            Command::TrackFromFilename => Some(&Field::Track),
+            Command::SplitVariousArtists => Some(&Field::AlbumArtist),
+            Command::CopyCover(_) | Command::AutoCover(_) => Some(&Field::Image),
+            _ => None,
+        }).collect();
+
+        if fields.is_empty() {
+            return;
+        }
+
+        let mut saved = match inner::read(path) {
+            Ok(t) => t,
+            Err(_) => {
+                self.warn(&format!("Warning: couldn't read `{}` back to check that every field was saved", f));
+                return;
+            }
+        };
+
+        for field in fields {
+            let dropped = match field {
+                // A cover is only missing if one was written, `AutoCover` may not have found one
+                Field::Image => written.album_cover().is_some_and(|written| {
+                    saved.album_cover().map(|p| p.data) != Some(written.data)
+                }),
+                _ => printout_field(&mut *saved, field, &Padding::default()) != printout_field(written, field, &Padding::default()),
+            };
+
+            if dropped {
+                self.warn(&format!("Warning: '{}' wasn't saved to `{}`, its tag format doesn't support the field", field_to_str(field), f));
+            }
+        }
+    }
+
+    /// Applies a single command to a tag that's already been read from `path`, writing anything it
+    /// prints to `out`. `f` is the file's name as it was given, which is what's shown in messages
+    /// and what names and paths are parsed from. Returns whether the tag was changed and needs to be
+    /// written.
+    fn apply_command(&self, tag: &mut dyn AudioTag, c: &Command, f: &str, path: &str, out: &mut dyn Write) -> Result<bool, Error> {
+        match c {
+            Command::Set(field, d) => {
+                match field {
+                    // Int Fields
+                    Field::Disc => {
+                        match d {
+                            Data::Int(i) => tag.set_disc_number(cmp::max(*i, 0) as u32),
+                            Data::IntOf(i, total) => {
+                                tag.set_disc_number(cmp::max(*i, 0) as u32);
+                                tag.set_total_discs(*total);
+                            }
+                            _ => return Err(self.wrong_data(f, field)),
+                        }
+                    }
+                    Field::Track => {
+                        match d {
+                            Data::Int(i) => tag.set_track_number(cmp::max(*i, 0) as u32),
+                            Data::IntOf(i, total) => {
+                                tag.set_track_number(cmp::max(*i, 0) as u32);
+                                tag.set_total_tracks(*total);
+                            }
+                            _ => return Err(self.wrong_data(f, field)),
+                        }
+                    }
+                    Field::Year => {
+                        if let Data::Int(i) = d {
+                            tag.set_year(*i);
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+
+                    // Title Fields
+                    Field::Title => {
+                        if let Data::Str(s) = d {
+                            tag.set_title(s);
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+                    Field::Artist => {
+                        if let Data::Str(s) = d {
+                            tag.set_artist(s);
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+                    Field::Album => {
+                        if let Data::Str(s) = d {
+                            tag.set_album_title(s);
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+                    Field::AlbumArtist => {
+                        if let Data::Str(s) = d {
+                            tag.set_album_artist(s);
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+                    Field::Genre => {
+                        if let Data::Str(s) = d {
+                            if inner::set_genre(tag, s).is_err() {
+                                let error_str = format!("Genres can't be edited in `{}`'s tag format", f);
+                                return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                            }
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+                    Field::Composer => {
+                        if let Data::Str(s) = d {
+                            if inner::set_composer(tag, s).is_err() {
+                                let error_str = format!("Composers can't be edited in `{}`'s tag format", f);
+                                return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                            }
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+                    Field::Comment => {
+                        if let Data::Str(s) = d {
+                            if inner::set_comment(tag, s).is_err() {
+                                let error_str = format!("Comments can't be edited in `{}`'s tag format", f);
+                                return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                            }
+                        }
+                        else { return Err(self.wrong_data(f, field)); }
+                    }
+
+                    // File Fields
+                    Field::Image => {
+                        let mut buf: Vec<u8> = Vec::new();
+                        
+                        if let Data::File(s) = d {
+                            let mut file = match File::open(s) {
+                                Ok(file) => file,
+                                Err(_) => {
+                                    let error_str = "Issue when opening image file.";
+                                    return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                                }
+                            };
+
+                            if file.read_to_end(&mut buf).is_err() {
+                                let error_str = "Issue when reading image file.";
+                                return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                            }
+                        }
+
+                        else if let Data::Bytes(b) = d {
+                            buf.extend_from_slice(b);
+                        }
+
+                        else { return Err(self.wrong_data(f, field)); }
+
+                        let reader = Reader::new(Cursor::new(&buf))
+                            .with_guessed_format().expect("'cursor io never fails'");
+                        let mimetype = match reader.format() {
+                            Some(ImageFormat::Png) => MimeType::Png,
+                            Some(ImageFormat::Jpeg) => MimeType::Jpeg,
+                            Some(ImageFormat::Tiff) => MimeType::Tiff,
+                            Some(ImageFormat::Bmp) => MimeType::Bmp,
+                            Some(ImageFormat::Gif) => MimeType::Gif,
+                            _ => {
+                                let error_str = "Unsupported image format (Supported: Png, Jpeg, Tiff, Bmp, Gif)";
+                                return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                            }
+                        };
+
+                        if self.validate_image && reader.decode().is_err() {
+                            let error_str = "The image couldn't be decoded, it may be corrupt or truncated";
+                            return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                        }
+
+                        let picture = Picture::new(&buf, mimetype);
+                        tag.set_album_cover(picture);
+                    }
+                }
+
+                Ok(true)
+            }
+            Command::TrackFromFilename => {
+                if tag.track_number().is_none() {
+                    if let Some(n) = leading_number(f) {
+                        tag.set_track_number(n);
+                        return Ok(true);
+                    }
+                }
+
+                Ok(false)
+            }
+            Command::ParsePath(levels) => {
+                let values = match match_path(f, levels) {
+                    Some(v) => v,
+                    None => {
+                        self.warn(&format!("`{}` doesn't fit the path pattern, skipping it", f));
+                        return Ok(false);
+                    }
+                };
+
+                let mut changed = false;
+
+                for (field, value) in values {
+                    if value.is_empty() || (field_present(tag, field) && !self.force) {
+                        continue;
+                    }
+
+                    let data = match field {
+                        Field::Track | Field::Year | Field::Disc => match value.parse::<i32>() {
+                            Ok(n) => Data::Int(n),
+                            Err(_) => {
+                                self.warn(&format!("Warning: '{}' from the path of `{}` isn't a number, so {} wasn't set", value, f, field_to_str(field)));
+                                continue;
+                            }
+                        },
+                        _ => Data::Str(value),
+                    };
+
+                    changed |= self.apply_command(tag, &Command::Set(*field, data), f, path, out)?;
+                }
+
+                Ok(changed)
+            }
+            Command::MapGenre(map) => {
+                let genre = match inner::genre(tag) {
+                    Ok(g) => g,
+                    Err(_) => {
+                        let error_str = format!("Genres can't be edited in `{}`'s tag format", f);
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                    }
+                };
+
+                if let Some(g) = genre {
+                    match map.get(&g.to_lowercase()) {
+                        Some(to) => {
+                            if *to != g {
+                                if inner::set_genre(tag, to).is_err() {
+                                    let error_str = format!("Genres can't be edited in `{}`'s tag format", f);
+                                    return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                                }
+                                return Ok(true);
+                            }
+                        }
+                        None => {
+                            if self.strict {
+                                self.warn(&format!("`{}` has a genre that isn't in the genre map: {}", f, g));
+                            }
+                        }
+                    }
+                }
+
+                Ok(false)
+            }
+            Command::SplitVariousArtists => {
+                tag.set_album_artist("Various Artists");
+
+                if inner::set_compilation(tag).is_err() {
+                    self.warn(&format!("Warning: `{}`'s tag format can't be marked as a compilation, only the album artist was set", f));
+                }
+
+                Ok(true)
+            }
+            Command::CopyCover(cover) => {
+                tag.set_album_cover(Picture::new(&cover.data, cover.mime_type));
+
+                if let Some(d) = &cover.description {
+                    let _ = inner::set_cover_description(tag, d);
+                }
+
+                Ok(true)
+            }
+            Command::AutoCover(names) => {
+                if tag.album_cover().is_some() {
+                    return Ok(false);
+                }
+
+                match find_cover(f, names) {
+                    Some(cover) => {
+                        let set = Command::Set(Field::Image, Data::File(cover.to_string_lossy().into_owned()));
+                        self.apply_command(tag, &set, f, path, out)
+                    }
+                    None => {
+                        self.warn(&format!("`{}` has no cover and there's no cover file next to it", f));
+                        Ok(false)
+                    }
+                }
+            }
+            Command::PrintRaw(key) => {
+                let value = match inner::raw(tag, key) {
+                    Ok(v) => v,
+                    Err(_) => {
+                        let error_str = format!("Raw keys can't be read from `{}`'s tag format", f);
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                    }
+                };
+
+                if writeln!(out, "{}", value.unwrap_or_default()).is_err() {
+                    let error_str = "Error when trying to print to stdout";
+                    return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                }
+
+                Ok(false)
+            }
+            Command::StripFrame(key) => {
+                let removed = match inner::strip(tag, key) {
+                    Ok(n) => n,
+                    Err(_) => {
+                        let error_str = format!("Frames can't be removed from `{}`'s tag format", f);
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                    }
+                };
+
+                self.warn(&format!("{} {} `{}` frame(s) from `{}`", self.verb("Removed", "Would remove"), removed, key, f));
+
+                Ok(removed > 0)
+            }
+            Command::MergeComments(separator) => {
+                let merged = match inner::merge_comments(tag, separator) {
+                    Ok(n) => n,
+                    Err(_) => {
+                        let error_str = format!("Comments can't be edited in `{}`'s tag format", f);
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                    }
+                };
+
+                if merged > 0 {
+                    self.warn(&format!("{} {} comments in `{}` into one", self.verb("Merged", "Would merge"), merged, f));
+                }
+
+                Ok(merged > 0)
+            }
+            Command::NormalizeFeat { style, move_to_artist } => {
+                let old_title = tag.title().unwrap_or("").to_string();
+                let old_artist = tag.artist_str().unwrap_or("").to_string();
+
+                let mut title = normalize_feat(&old_title, style);
+                let mut artist = normalize_feat(&old_artist, style);
+
+                if *move_to_artist {
+                    if let Some((rest, featured)) = split_feat(&title) {
+                        let named = artist.to_lowercase().contains(&featured.to_lowercase());
+                        let has_feat = artist.split(' ').any(|w| w == style || is_feat(w));
+
+                        // Leave the artist alone when it already names them
+                        if artist.is_empty() {
+                            artist = featured;
+                        } else if !named && has_feat {
+                            artist = format!("{}, {}", artist, featured);
+                        } else if !named {
+                            artist = format!("{} {} {}", artist, style, featured);
+                        }
+                        title = rest;
+                    }
+                }
+
+                let mut changed = false;
+
+                if title != old_title {
+                    self.warn(&format!("`{}`: title \"{}\" -> \"{}\"", f, old_title, title));
+                    tag.set_title(&title);
+                    changed = true;
+                }
+
+                if artist != old_artist {
+                    self.warn(&format!("`{}`: artist \"{}\" -> \"{}\"", f, old_artist, artist));
+                    tag.set_artist(&artist);
+                    changed = true;
+                }
+
+                Ok(changed)
+            }
+            Command::ExtractImage(path) => {
+                let cover = match tag.album_cover() {
+                    Some(p) => p,
+                    None => {
+                        let error_str = format!("`{}` has no image to extract", f);
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                    }
+                };
+
+                let ext = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+                let expected = mime_to_ext(cover.mime_type);
+                let matching = ext == expected || matches!((ext.as_str(), expected), ("jpeg", "jpg") | ("tif", "tiff"));
+
+                if !matching {
+                    let mime: &str = cover.mime_type.into();
+                    self.warn(&format!("Warning: the image in `{}` is {}, but `{}` doesn't end in .{}", f, mime, path, expected));
+                }
+
+                if self.dry_run {
+                    self.warn(&format!("Would write the image of `{}` to {}", f, path));
+                } else if fs::write(path, cover.data).is_err() {
+                    let error_str = format!("Failed to write image to {}", path);
+                    return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                }
+
+                Ok(false)
+            }
+            Command::StripControlChars => {
+                let mut changed = false;
+
+                for field in &TEXT_FIELDS {
+                    if !field_present(tag, field) {
+                        continue;
+                    }
+
+                    let old = field_value(tag, field, &Padding::default());
+                    let new: String = old.chars().filter(|c| !is_stray(*c)).collect();
+
+                    if new != old {
+                        self.warn(&format!("`{}`: {} stray characters from {}", f, self.verb("removed", "would remove"), field_to_str(field)));
+                        let command = if new.is_empty() { Command::Clear(*field) } else { Command::Set(*field, Data::Str(new)) };
+                        self.apply_command(tag, &command, f, path, out)?;
+                        changed = true;
+                    }
+                }
+
+                Ok(changed)
+            }
+            Command::CapValues(max) => {
+                let capped = match inner::cap_values(tag, *max) {
+                    Ok(c) => c,
+                    Err(_) => {
+                        let error_str = format!("Values can't be counted in `{}`'s tag format", f);
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                    }
+                };
+
+                for (key, dropped) in &capped {
+                    self.warn(&format!("{} {} value(s) from `{}` in `{}`", self.verb("Dropped", "Would drop"), dropped, key, f));
+                }
+
+                Ok(!capped.is_empty())
+            }
+            Command::ReencodeUtf8 => {
+                // Frames that aren't in ID3v2 tags are always UTF-8 already
+                let frames = fs::read(path).ok().and_then(|d| encoding::frame_encodings(&d)).unwrap_or_default();
+                let legacy: Vec<String> = frames.into_iter()
+                    .filter(|(_, e)| *e != encoding::TextEncoding::Utf8)
+                    .map(|(id, e)| format!("{} ({})", id, e.name()))
+                    .collect();
+
+                if legacy.is_empty() {
+                    return Ok(false);
+                }
+
+                // Every frame is written out as UTF-8 when the tag is saved as ID3v2.4
+                self.warn(&format!("{} {} in `{}` as UTF-8", self.verb("Re-encoding", "Would re-encode"), legacy.join(", "), f));
+
+                Ok(true)
+            }
+            Command::Clear(field) => {
+                match field {
+                    // Int Fields
+                    Field::Disc => tag.remove_disc_number(),
+                    Field::Track => tag.remove_track_number(),
+                    Field::Year => tag.remove_year(),
+
+                    // Str Fields
+                    Field::Title => tag.remove_title(),
+                    Field::Artist => tag.remove_artist(),
+                    Field::Album => tag.remove_album_title(),
+                    Field::AlbumArtist => tag.remove_album_artists(),
+                    Field::Genre => {
+                        if inner::remove_genre(tag).is_err() {
+                            let error_str = format!("Genres can't be edited in `{}`'s tag format", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                        }
+                    }
+                    Field::Composer => {
+                        if inner::remove_composer(tag).is_err() {
+                            let error_str = format!("Composers can't be edited in `{}`'s tag format", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                        }
+                    }
+                    Field::Comment => {
+                        if inner::remove_comment(tag).is_err() {
+                            let error_str = format!("Comments can't be edited in `{}`'s tag format", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                        }
+                    }
+
+                    // File Fields
+                    Field::Image => tag.remove_album_cover(),
+                }
+
+                if self.clear_related {
+                    match inner::strip_sort(tag, field_to_str(field)) {
+                        Ok(0) => {}
+                        Ok(n) => self.warn(&format!("{} {} {} sort order frame(s) from `{}`", self.verb("Removed", "Would remove"), n, field_to_str(field), f)),
+                        Err(_) => self.warn(&format!("Warning: sort order frames can't be removed from `{}`'s tag format", f)),
+                    }
+                }
+
+                Ok(true)
+            }
+            Command::ClearAll => {
+                tag.remove_disc_number();
+                tag.remove_track_number();
+                tag.remove_year();
+                tag.remove_title();
+                tag.remove_artist();
+                tag.remove_album_title();
+                tag.remove_album_artists();
+                tag.remove_album_cover();
+
+                // Formats that can't hold these have nothing to clear
+                inner::remove_genre(tag).ok();
+                inner::remove_composer(tag).ok();
+                inner::remove_comment(tag).ok();
+
+                Ok(true)
+            }
+            Command::Print(field) => {
+                let printed = match field {
+                    // Int Fields
+                    Field::Disc => writeln!(out, "{:0width$}", tag.disc_number().unwrap_or(0), width = self.padding.disc),
+                    Field::Track => writeln!(out, "{:0width$}", tag.track_number().unwrap_or(0), width = self.padding.track),
+                    Field::Year => writeln!(out, "{}", tag.year().unwrap_or(0)),
+
+                    // Str Fields
+                    Field::Title => writeln!(out, "{}", tag.title().unwrap_or("")),
+                    Field::Artist => writeln!(out, "{}", tag.artist_str().unwrap_or("")),
+                    Field::Album => writeln!(out, "{}", tag.album_title().unwrap_or("")),
+                    Field::AlbumArtist => writeln!(out, "{}", tag.album_artist_str().unwrap_or("")),
+                    Field::Genre => match inner::genre(tag) {
+                        Ok(g) => writeln!(out, "{}", g.unwrap_or_default()),
+                        Err(_) => {
+                            let error_str = format!("Genres can't be read from `{}`'s tag format", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                        }
+                    },
+                    Field::Composer => match inner::composer(tag) {
+                        Ok(c) => writeln!(out, "{}", c.unwrap_or_default()),
+                        Err(_) => {
+                            let error_str = format!("Composers can't be read from `{}`'s tag format", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                        }
+                    },
+                    Field::Comment => match inner::comment(tag) {
+                        Ok(c) => writeln!(out, "{}", c.unwrap_or_default()),
+                        Err(_) => {
+                            let error_str = format!("Comments can't be read from `{}`'s tag format", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 7));
+                        }
+                    },
+
+                    // File Fields
+                    Field::Image => match tag.album_cover() {
+                        // Write a newline separator after the image
+                        Some(p) => out.write_all(p.data).and_then(|_| writeln!(out)),
+                        None => writeln!(out),
+                    },
+                };
+
+                if printed.is_err() {
+                    // This error message probably won't even make it to the user, lol.
+                    let error_str = "Error when trying to print to stdout";
+                    return Err(Error::new(&self.name, &self.opts, Some(error_str), 2));
+                }
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Writes the cover of `tag` next to `f` for `--write-cover-to-sidecar`. An existing file with
+    /// the same image is left alone without a word, since every track of an album writes the same
+    /// cover.
+    fn write_sidecar(&self, f: &str, tag: &dyn AudioTag, name: &str) -> Result<(), Error> {
+        let cover = match tag.album_cover() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let name = if name.is_empty() { format!("folder.{}", mime_to_ext(cover.mime_type)) } else { name.to_string() };
+        let path = Path::new(f).parent().unwrap_or_else(|| Path::new("")).join(name);
+
+        if path.exists() && !self.force {
+            if fs::read(&path).ok().as_deref() != Some(cover.data) {
+                self.warn(&format!("`{}` already exists and isn't `{}`'s cover, not replacing it without --force", path.display(), f));
+            }
+            return Ok(());
+        }
+
+        if self.dry_run {
+            self.warn(&format!("Would write the cover of `{}` to {}", f, path.display()));
+        } else if fs::write(&path, cover.data).is_err() {
+            let error_str = format!("Failed to write image to {}", path.display());
+            return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+        }
+
+        Ok(())
+    }
+
+    /// Runs every option on a single file. Returns whether the file was changed. The file is read
+    /// and written at `path`, which is `f` itself unless it was staged for `--transaction`.
+    fn exec_file(&self, f: &str, path: &str, out: &mut dyn Write) -> Result<bool, Error> {
+        let mut modified = false;
+
+        // Repair first, so the moved tag is the one that gets read
+        if self.repair {
+            match repair::repair(path) {
+                Ok(Repair::Moved) => {
+                    self.warn(&format!("Repaired `{}`: moved its ID3v2 tag from the end to the start", f));
+                    modified = true;
+                }
+                Ok(Repair::NotNeeded) => self.warn(&format!("`{}` didn't need repairing", f)),
+                Ok(Repair::Conflict) => self.warn(&format!("Warning: `{}` has ID3v2 tags at both ends, not repairing it", f)),
+                Err(_) => {
+                    let err_str = format!("Failed to repair `{}`", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 2));
+                }
+            }
+        }
+
+        let mut tag = match inner::read(path) {
+            Ok(t) => t,
+            Err(_) => { 
+                let err_str = format!("Failure to open `{}` for editing", f);
+                return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+            }
+        };
+        
+        // Only read when something is printed that needs them, since it can mean reading the whole
+        // file. Properties that can't be read are left unknown.
+        let audio = OnceCell::new();
+        let properties = || audio.get_or_init(|| properties::read(path).unwrap_or_default());
+
+        if let Some(limit) = self.tag_size_limit {
+            match properties().tag_size {
+                Some(size) if size > limit => {
+                    let err_str = format!("`{}`'s tags take up {} bytes, more than the limit of {}", f, size, limit);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 9));
+                }
+                Some(_) => {}
+                None => self.warn(&format!("Warning: couldn't measure the size of `{}`'s tags", f)),
+            }
+        }
+
+        // --print-modified-only and --compact already name the files they print, and --json gives the
+        // path of each object
+        if self.print_path && !self.modified_only && !self.compact && !self.json {
+            self.emit(out, &format!("{}:", self.shown(f)))?;
+        }
+
+        if self.properties {
+            self.emit(out, &printout_properties(properties()))?;
+        }
+
+        if let Some(dir) = &self.export_image_dir {
+            match tag.album_cover() {
+                Some(p) => {
+                    let stem = Path::new(f).file_stem().unwrap_or_default().to_string_lossy();
+                    let path = Path::new(dir).join(format!("{}.{}", stem, mime_to_ext(p.mime_type)));
+
+                    // Files with the same name in different directories would otherwise overwrite
+                    // each other's images
+                    if path.exists() && !self.force {
+                        let error_str = format!("{} already exists, not replacing it without --force", path.display());
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                    }
+
+                    if self.dry_run {
+                        self.warn(&format!("Would write the image of `{}` to {}", f, path.display()));
+                    } else if fs::write(&path, p.data).is_err() {
+                        let error_str = format!("Failed to write image to {}", path.display());
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                    }
+                }
+                None => self.warn(&format!("`{}` has no image, skipping", f)),
+            }
+        }
+
+        if self.dump_image {
+            match tag.album_cover() {
+                Some(p) => {
+                    let path = Path::new(f).with_extension(mime_to_ext(p.mime_type));
+
+                    if path.exists() && !self.force {
+                        let error_str = format!("{} already exists, not replacing it without --force", path.display());
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                    }
+
+                    if self.dry_run {
+                        self.warn(&format!("Would write the image of `{}` to {}", f, path.display()));
+                    } else if fs::write(&path, p.data).is_err() {
+                        let error_str = format!("Failed to write image to {}", path.display());
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                    }
+                }
+                None => self.warn(&format!("`{}` has no image, skipping", f)),
+            }
+        }
+
+        if self.print_encoding {
+            let frames = match fs::read(path).ok().and_then(|d| encoding::frame_encodings(&d)) {
+                Some(frames) => frames,
+                None => {
+                    let err_str = format!("`{}` has no ID3v2 tag that its encodings can be read from", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            for (id, encoding) in frames {
+                self.emit(out, &format!("{}: {}", id, encoding.name()))?;
+            }
+        }
+
+        if let Some(field) = &self.field_json {
+            self.emit(out, &field_json(&self.shown(f), &mut *tag, field))?;
+        }
+
+        if self.print_hash {
+            self.emit(out, &format!("{:016x}  {}", tag_hash(&mut *tag), self.shown(f)))?;
+        }
+
+        if self.art_info {
+            match tag.album_cover() {
+                Some(p) => self.emit(out, &format!("{}: {}", self.shown(f), art_info(&p)))?,
+                None => self.emit(out, &format!("{}: no image", self.shown(f)))?,
+            }
+        }
+
+        if self.tag_count {
+            // ID3v2 tags are walked directly, since the id3 crate merges frames that repeat
+            let keys = match fs::read(path).ok().and_then(|d| encoding::frame_keys(&d)) {
+                Some(keys) => Ok(keys),
+                None => inner::all(&mut *tag).map(|frames| frames.into_iter().map(|(k, _)| k).collect()),
+            };
+
+            let keys = match keys {
+                Ok(keys) => keys,
+                Err(_) => {
+                    let err_str = format!("The frames of `{}`'s tag format can't be counted", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            self.emit(out, &printout_tag_count(&keys))?;
+        }
+
+        if self.all_tags_json {
+            let frames = match inner::all(&mut *tag) {
+                Ok(frames) => frames,
+                Err(_) => {
+                    let err_str = format!("The frames of `{}`'s tag format can't be listed", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&err_str), 7));
+                }
+            };
+
+            self.emit(out, &all_tags_json(&self.shown(f), &frames))?;
+        }
+
+        let commands = self.commands_for(f);
+
+        if commands.is_empty() {
+            if !self.replaces_printout() {
+                self.show_default_printout(f, &mut *tag, properties(), out)?;
+            }
+        } else {
+            let mut need_to_write = false;
+            let mut did_print = self.replaces_printout();
+            let before = if self.modified_only || self.changed_fields || self.dry_run { Some(Snapshot::new(&mut *tag, &self.padding)) } else { None };
+
+            // With `--json`, printed fields go into the file's object once every command has run
+            let mut json_fields: Vec<Field> = Vec::new();
+
+            for c in commands {
+                if let (true, Command::Print(field)) = (self.json, c) {
+                    json_fields.push(*field);
+                    continue;
+                }
+
+                if self.apply_command(&mut *tag, c, f, path, out)? {
+                    need_to_write = true;
+                }
+
+                if let Command::Print(_) | Command::PrintRaw(_) | Command::ExtractImage(_) = c {
+                    did_print = true;
+                }
+            }
+
+            if self.dry_run && self.json {
+                let changes = before.as_ref().map(|b| b.json_changes(&mut *tag, &self.padding)).unwrap_or_default();
+                self.json_objects.lock().unwrap().push((f.to_string(), preview_json(&self.shown(f), need_to_write, &changes)));
+
+                did_print = true;
+                json_fields.clear();
+            } else if need_to_write && self.dry_run {
+                let changes = before.as_ref().map(|b| b.changes(&mut *tag, &self.padding)).unwrap_or_default();
+
+                if changes.is_empty() {
+                    self.emit(out, &format!("{}: would be rewritten, without changing any of the fields shown", self.shown(f)))?;
+                } else {
+                    self.emit(out, &format!("{}: would change", self.shown(f)))?;
+                    for c in changes {
+                        self.emit(out, &format!("  {}", c))?;
+                    }
+                }
+
+                did_print = true;
+            } else if need_to_write {
+                // The whole file is kept, so it can be put back if the audio changes
+                let original = if self.guard_audio {
+                    match fs::read(path) {
+                        Ok(data) => match audio_checksum(&data) {
+                            Some(checksum) => Some((data, checksum)),
+                            None => {
+                                self.warn(&format!("Warning: couldn't find the audio in `{}`, so it isn't guarded", f));
+                                None
+                            }
+                        },
+                        Err(_) => {
+                            let err_str = format!("Failed to read `{}` to checksum its audio", f);
+                            return Err(Error::new(&self.name, &self.opts, Some(&err_str), 2));
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                let written = match self.id3_version.map(|v| inner::write_id3(&mut *tag, path, v)) {
+                    Some(Ok(w)) => w,
+                    // Not an ID3v2 tag, so the version doesn't apply
+                    Some(Err(_)) | None => tag.write_to_path(path).is_ok(),
+                };
+
+                if !written {
+                    let error_str = format!("Failed to write new tags to {}", f);
+                    return Err(Error::new(&self.name, &self.opts, Some(&error_str), 2));
+                }
+
+                if let Some((data, checksum)) = original {
+                    if fs::read(path).ok().and_then(|d| audio_checksum(&d)) != Some(checksum) {
+                        let error_str = match fs::write(path, &data) {
+                            Ok(_) => format!("Writing the tag of `{}` changed its audio, so the file was put back the way it was", f),
+                            Err(_) => format!("Writing the tag of `{}` changed its audio, and the file couldn't be put back", f),
+                        };
+                        return Err(Error::new(&self.name, &self.opts, Some(&error_str), 9));
+                    }
+                }
+
+                modified = true;
+
+                if self.no_read_after_write {
+                    did_print = true;
+                } else {
+                    self.warn_dropped_fields(f, path, &mut *tag);
+                }
+            }
+
+            if let Some(before) = before.as_ref().filter(|_| self.changed_fields) {
+                let changed = before.changed_fields(&mut *tag, &self.padding);
+
+                if !changed.is_empty() {
+                    self.emit(out, &format!("{}: {}", self.shown(f), changed.join(", ")))?;
+                }
+            }
+
+            if let Some(before) = before.filter(|_| self.modified_only) {
+                let changes = before.changes(&mut *tag, &self.padding);
+
+                if !changes.is_empty() {
+                    self.emit(out, &format!("{}:", self.shown(f)))?;
+                    for c in changes {
+                        self.emit(out, &format!("  {}", c))?;
+                    }
+                }
+            }
+
+            if !json_fields.is_empty() {
+                self.json_objects.lock().unwrap().push((f.to_string(), printout_json(&self.shown(f), &mut *tag, properties(), &json_fields)));
+            } else if !did_print && !self.quiet {
+                self.show_default_printout(f, &mut *tag, properties(), out)?;
+            }
+        }
+
+        if let Some(name) = &self.sidecar {
+            self.write_sidecar(f, &*tag, name)?;
+        }
+
+        if self.require_image && tag.album_cover().is_none() {
+            let err_str = format!("`{}` has no cover", f);
+            return Err(Error::new(&self.name, &self.opts, Some(&err_str), 9));
+        }
+
+        Ok(modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A short, silent MP3 with an empty ID3v2.4 tag in front of it
+    fn mp3() -> Vec<u8> {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+
+        // MPEG-1 Layer III frames at 128kbps and 44.1kHz, which are 417 bytes long
+        for _ in 0..20 {
+            data.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+            data.extend_from_slice(&[0; 413]);
+        }
+
+        data
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// Runs `exec_bytes` on `data` as if it were called `name`, returning the modified file and
+    /// what was printed
+    fn run_bytes(a: &[&str], name: &str, data: &[u8]) -> Result<(Vec<u8>, String), Error> {
+        let mut config = Config::new_in_memory(&args(a), "insignia")?;
+        let mut out = Vec::new();
+        let data = config.exec_bytes(name, data, &mut out)?;
+
+        Ok((data, String::from_utf8(out).unwrap()))
+    }
+
+    /// An ID3v2 tag with whatever `frames` adds to it
+    fn id3_tag(frames: impl FnOnce(&mut id3::Tag)) -> Box<dyn AudioTag> {
+        let mut tag = id3::Tag::new();
+        frames(&mut tag);
+
+        Box::new(lofty::Id3v2Tag::from(tag))
+    }
+
+    /// Runs `command` on `tag`, returning whether it changed and what was printed
+    fn apply(tag: &mut Box<dyn AudioTag>, command: Command) -> (bool, String) {
+        let mut out = Vec::new();
+        let changed = apply_command(tag.as_mut(), &command, &mut out).unwrap();
+
+        (changed, String::from_utf8(out).unwrap())
+    }
+
+    /// A new, empty directory for a test to put files in
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("insignia-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    /// A writer that can still be read from after it's given to `warnings_to`
+    #[derive(Clone, Default)]
+    struct Shared(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    /// Runs `exec_to` with `a` as the arguments, returning how it went, what was printed, and the
+    /// warnings
+    fn run(a: &[&str]) -> (Result<(), Error>, String, String) {
+        let warnings = Shared::default();
+        let mut out = Vec::new();
+        let result = Config::new(&args(a), "insignia").and_then(|mut config| {
+            config.warnings_to(warnings.clone());
+            config.exec_to(&mut out)
+        });
+
+        (result, String::from_utf8(out).unwrap(), warnings.text())
+    }
+
+    /// Writes `data` to each of `names` in `dir`, returning their paths
+    fn write_files(dir: &Path, names: &[&str], data: &[u8]) -> Vec<String> {
+        names.iter().map(|name| {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            path.to_string_lossy().into_owned()
+        }).collect()
+    }
+
+    /// `mp3` with a title in Latin-1, an artist in UTF-16, and an album in UTF-8
+    fn legacy_mp3() -> Vec<u8> {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x35".to_vec();
+        data.extend_from_slice(b"TIT2\x00\x00\x00\x05\x00\x00\x00Caf\xe9");
+        data.extend_from_slice(b"TPE1\x00\x00\x00\x0b\x00\x00\x01\xff\xfeB\x00a\x00n\x00d\x00");
+        data.extend_from_slice(b"TALB\x00\x00\x00\x07\x00\x00\x03Record");
+        data.extend_from_slice(&mp3()[10..]);
+
+        data
+    }
+
+    /// A black PNG image of the given size
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(width, height))
+            .write_to(&mut data, image::ImageOutputFormat::Png).unwrap();
+
+        data
+    }
+
+    /// A front cover frame of PNG `data`
+    fn front_cover(data: Vec<u8>) -> id3::frame::Picture {
+        id3::frame::Picture {
+            mime_type: "image/png".to_string(),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data,
+        }
+    }
+
+    /// Writes an ID3v2.4 tag with `frames` in it over the tag of the file at `path`
+    fn write_tag(path: &str, frames: impl FnOnce(&mut id3::Tag)) {
+        let mut tag = id3::Tag::new();
+        frames(&mut tag);
+        tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+    }
+
+    fn cover() -> Cover {
+        Cover { data: b"\x89PNG not really".to_vec(), mime_type: MimeType::Png, description: Some("Front".to_string()) }
+    }
+
+    #[test]
+    fn exec_bytes_round_trip() {
+        let (data, _) = run_bytes(&["--title=Hello", "--artist=Someone", "--year=2001"], "song.mp3", &mp3()).unwrap();
+
+        let (_, out) = run_bytes(&["--title", "--artist"], "song.mp3", &data).unwrap();
+        assert_eq!(out, "Hello\nSomeone\n");
+
+        let (_, out) = run_bytes(&["--year"], "song.mp3", &data).unwrap();
+        assert_eq!(out, "2001\n");
+    }
+
+    #[test]
+    fn exec_bytes_leaves_the_input_alone() {
+        let original = mp3();
+        let (data, _) = run_bytes(&["--title=Hello"], "song.mp3", &original).unwrap();
+
+        assert_ne!(data, original);
+        assert_eq!(original, mp3());
+    }
+
+    #[test]
+    fn exec_bytes_prints_to_out() {
+        let (_, out) = run_bytes(&["--title=Hello"], "song.mp3", &mp3()).unwrap();
+
+        assert!(out.contains("Title: Hello\n"));
+        assert!(out.contains("Length: 0:01\n"));
+    }
+
+    #[test]
+    fn exec_bytes_uses_the_given_name() {
+        let (data, _) = run_bytes(&["--normalize-track-from-filename"], "07 Song.mp3", &mp3()).unwrap();
+        let (_, out) = run_bytes(&["--track"], "07 Song.mp3", &data).unwrap();
+        assert_eq!(out, "7\n");
+
+        let (data, _) = run_bytes(&["--parse-from-directory-structure", "--path-pattern={artist}/{album}/{title}"], "Band/Record/Tune.mp3", &mp3()).unwrap();
+        let (_, out) = run_bytes(&["--artist", "--album", "--title"], "Band/Record/Tune.mp3", &data).unwrap();
+        assert_eq!(out, "Tune\nBand\nRecord\n");
+    }
+
+    #[test]
+    fn exec_bytes_json() {
+        let (_, out) = run_bytes(&["--json", "--only=title", "--title=Hello"], "song.mp3", &mp3()).unwrap();
+        assert_eq!(out, "{\"path\":\"song.mp3\",\"title\":\"Hello\"}\n");
+    }
+
+    #[test]
+    fn exec_bytes_rejects_other_data() {
+        let e = run_bytes(&["--title=Hello"], "song.mp3", b"not a music file").unwrap_err();
+        assert_eq!(e.error_code, 7);
+    }
+
+    #[test]
+    fn command_print() {
+        let mut tag = id3_tag(|t| {
+            t.set_title("Hello");
+            t.set_year(1999);
+        });
+
+        assert_eq!(apply(&mut tag, Command::Print(Field::Title)), (false, "Hello\n".to_string()));
+        assert_eq!(apply(&mut tag, Command::Print(Field::Year)), (false, "1999\n".to_string()));
+        assert_eq!(apply(&mut tag, Command::Print(Field::Artist)), (false, "\n".to_string()));
+    }
+
+    #[test]
+    fn command_clear() {
+        let mut tag = id3_tag(|t| {
+            t.set_title("Hello");
+            t.set_artist("Someone");
+        });
+
+        assert!(apply(&mut tag, Command::Clear(Field::Title)).0);
+        assert_eq!(tag.title(), None);
+        assert_eq!(tag.artist_str(), Some("Someone"));
+    }
+
+    #[test]
+    fn command_clear_all() {
+        let mut tag = id3_tag(|t| {
+            t.set_title("Hello");
+            t.set_genre("Rock");
+            t.set_track(3);
+        });
+
+        assert!(apply(&mut tag, Command::ClearAll).0);
+        assert_eq!(tag.title(), None);
+        assert_eq!(tag.track_number(), None);
+        assert_eq!(inner::genre(tag.as_mut()).unwrap(), None);
+    }
+
+    #[test]
+    fn command_set() {
+        let mut tag = id3_tag(|_| {});
+
+        assert!(apply(&mut tag, Command::Set(Field::Title, Data::Str("Hello".to_string()))).0);
+        assert!(apply(&mut tag, Command::Set(Field::Track, Data::IntOf(3, 12))).0);
+        assert!(apply(&mut tag, Command::Set(Field::Genre, Data::Str("Rock".to_string()))).0);
+
+        assert_eq!(tag.title(), Some("Hello"));
+        assert_eq!(tag.track(), (Some(3), Some(12)));
+        assert_eq!(inner::genre(tag.as_mut()).unwrap(), Some("Rock".to_string()));
+    }
+
+    #[test]
+    fn command_map_genre() {
+        let map: HashMap<String, String> = vec![("hip hop".to_string(), "Hip-Hop".to_string())].into_iter().collect();
+        let mut tag = id3_tag(|t| t.set_genre("hip hop"));
+
+        assert!(apply(&mut tag, Command::MapGenre(map.clone())).0);
+        assert_eq!(inner::genre(tag.as_mut()).unwrap(), Some("Hip-Hop".to_string()));
+
+        // Already in its canonical form
+        assert!(!apply(&mut tag, Command::MapGenre(map)).0);
+    }
+
+    #[test]
+    fn command_split_various_artists() {
+        let mut tag = id3_tag(|t| t.set_album_artist("Someone"));
+
+        assert!(apply(&mut tag, Command::SplitVariousArtists).0);
+        assert_eq!(tag.album_artist_str(), Some("Various Artists"));
+        assert_eq!(inner::raw(tag.as_mut(), "TCMP").unwrap(), Some("1".to_string()));
+    }
+
+    #[test]
+    fn command_copy_cover() {
+        let mut tag = id3_tag(|_| {});
+
+        assert!(apply(&mut tag, Command::CopyCover(cover())).0);
+        assert_eq!(tag.album_cover().map(|p| p.data.to_vec()), Some(cover().data));
+        assert_eq!(inner::cover_description(tag.as_mut()).unwrap(), Some("Front".to_string()));
+    }
+
+    #[test]
+    fn command_print_raw() {
+        let mut tag = id3_tag(|t| t.set_text("TMOO", "Calm"));
+
+        assert_eq!(apply(&mut tag, Command::PrintRaw("TMOO".to_string())), (false, "Calm\n".to_string()));
+        assert_eq!(apply(&mut tag, Command::PrintRaw("TKEY".to_string())), (false, "\n".to_string()));
+    }
+
+    #[test]
+    fn command_strip_frame() {
+        let mut tag = id3_tag(|t| {
+            t.set_text("TMOO", "Calm");
+            t.set_title("Hello");
+        });
+
+        assert!(apply(&mut tag, Command::StripFrame("TMOO".to_string())).0);
+        assert_eq!(inner::raw(tag.as_mut(), "TMOO").unwrap(), None);
+        assert_eq!(tag.title(), Some("Hello"));
+
+        assert!(!apply(&mut tag, Command::StripFrame("TMOO".to_string())).0);
+    }
+
+    #[test]
+    fn command_merge_comments() {
+        let comment = |description: &str, text: &str| id3::frame::Comment {
+            lang: "eng".to_string(),
+            description: description.to_string(),
+            text: text.to_string(),
+        };
+        let mut tag = id3_tag(|t| {
+            t.add_comment(comment("", "One"));
+            t.add_comment(comment("other", "Two"));
+        });
+
+        assert!(apply(&mut tag, Command::MergeComments(" / ".to_string())).0);
+        assert_eq!(inner::comment(tag.as_mut()).unwrap(), Some("One / Two".to_string()));
+
+        assert!(!apply(&mut tag, Command::MergeComments(" / ".to_string())).0);
+    }
+
+    #[test]
+    fn command_cap_values() {
+        let mut tag = id3_tag(|t| t.set_artist("One\0Two\0Three"));
+
+        assert!(apply(&mut tag, Command::CapValues(2)).0);
+        assert_eq!(inner::raw(tag.as_mut(), "TPE1").unwrap(), Some("One\0Two".to_string()));
+
+        assert!(!apply(&mut tag, Command::CapValues(2)).0);
+    }
+
+    #[test]
+    fn command_normalize_feat() {
+        let mut tag = id3_tag(|t| {
+            t.set_title("Song (featuring Guest)");
+            t.set_artist("Band ft. Other");
+        });
+
+        assert!(apply(&mut tag, Command::NormalizeFeat { style: "feat.".to_string(), move_to_artist: false }).0);
+        assert_eq!(tag.title(), Some("Song (feat. Guest)"));
+        assert_eq!(tag.artist_str(), Some("Band feat. Other"));
+
+        assert!(apply(&mut tag, Command::NormalizeFeat { style: "feat.".to_string(), move_to_artist: true }).0);
+        assert_eq!(tag.title(), Some("Song"));
+        assert_eq!(tag.artist_str(), Some("Band feat. Other, Guest"));
+    }
+
+    #[test]
+    fn command_strip_control_chars() {
+        let mut tag = id3_tag(|t| {
+            t.set_title("\u{FEFF}Hello\u{1}");
+            t.set_album("\u{FEFF}");
+        });
+
+        assert!(apply(&mut tag, Command::StripControlChars).0);
+        assert_eq!(tag.title(), Some("Hello"));
+        assert_eq!(tag.album_title(), None);
+
+        assert!(!apply(&mut tag, Command::StripControlChars).0);
+    }
+
+    #[test]
+    fn command_extract_image() {
+        let dir = temp_dir("extract");
+        let path = dir.join("cover.png").to_string_lossy().into_owned();
+
+        let mut tag = id3_tag(|_| {});
+        let mut out = Vec::new();
+        let e = apply_command(tag.as_mut(), &Command::ExtractImage(path.clone()), &mut out).unwrap_err();
+        assert_eq!(e.error_code, 2);
+
+        apply(&mut tag, Command::CopyCover(cover()));
+        assert!(!apply(&mut tag, Command::ExtractImage(path.clone())).0);
+        assert_eq!(fs::read(&path).unwrap(), cover().data);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn commands_that_need_a_file() {
+        let commands = vec![
+            Command::TrackFromFilename,
+            Command::ParsePath(vec![vec![PathToken::Field(Field::Title)]]),
+            Command::AutoCover(vec!["cover.jpg".to_string()]),
+            Command::ReencodeUtf8,
+        ];
+
+        for command in commands {
+            let mut tag = id3_tag(|t| t.set_title("Hello"));
+            let mut out = Vec::new();
+
+            let e = apply_command(tag.as_mut(), &command, &mut out).unwrap_err();
+            assert_eq!(e.error_code, 1);
+            assert_eq!(tag.title(), Some("Hello"));
+        }
+    }
+
+    #[test]
+    fn from_commands_accepts_matching_data() {
+        let commands = vec![
+            Command::Set(Field::Track, Data::IntOf(3, 12)),
+            Command::Set(Field::Disc, Data::Int(1)),
+            Command::Set(Field::Year, Data::Int(2001)),
+            Command::Set(Field::Title, Data::Str("Hello".to_string())),
+            Command::Set(Field::Image, Data::Bytes(Vec::new())),
+            Command::Set(Field::Comment, Data::StdIn),
+            Command::Print(Field::Title),
+        ];
+
+        assert!(Config::from_commands(commands, vec!["song.mp3".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn from_commands_rejects_mismatched_data() {
+        let fields = [
+            Field::Track, Field::Year, Field::Disc, Field::Title, Field::Artist, Field::Album,
+            Field::AlbumArtist, Field::Genre, Field::Composer, Field::Comment, Field::Image,
+        ];
+        let data = || vec![
+            Data::Str("Hello".to_string()),
+            Data::Int(3),
+            Data::IntOf(3, 12),
+            Data::File("cover.png".to_string()),
+            Data::Bytes(Vec::new()),
+        ];
+
+        for field in &fields {
+            for d in data() {
+                let fits = match field {
+                    Field::Track | Field::Disc => matches!(d, Data::Int(_) | Data::IntOf(..)),
+                    Field::Year => matches!(d, Data::Int(_)),
+                    Field::Image => matches!(d, Data::File(_) | Data::Bytes(_)),
+                    _ => matches!(d, Data::Str(_)),
+                };
+                let result = Config::from_commands(vec![Command::Set(*field, d)], Vec::new());
+
+                match result {
+                    Ok(_) => assert!(fits, "{} took data it can't hold", field_to_str(field)),
+                    Err(e) => {
+                        assert!(!fits, "{} refused data it can hold", field_to_str(field));
+                        assert_eq!(e.error_code, 1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn apply_command_rejects_mismatched_data() {
+        let mut tag = id3_tag(|_| {});
+        let mut out = Vec::new();
+
+        let e = apply_command(tag.as_mut(), &Command::Set(Field::Track, Data::Str("3".to_string())), &mut out).unwrap_err();
+        assert_eq!(e.error_code, 7);
+        assert_eq!(tag.track_number(), None);
+    }
+
+    #[test]
+    fn jobs_reports_the_files_finished_after_a_failure() {
+        let dir = temp_dir("jobs");
+        let mut files = Vec::new();
+
+        for i in 0..8 {
+            let path = dir.join(format!("{}.mp3", i));
+            fs::write(&path, mp3()).unwrap();
+            files.push(path);
+        }
+
+        // The files after it are already being worked on by the other jobs when it fails
+        let bad = dir.join("bad.mp3");
+        fs::write(&bad, b"not a music file").unwrap();
+        files.insert(1, bad.clone());
+
+        let mut a = vec!["--jobs=4".to_string(), "--summary-json".to_string(), "--quiet".to_string(), "--title=Hello".to_string()];
+        a.extend(files.iter().map(|f| f.to_string_lossy().into_owned()));
+
+        let mut out = Vec::new();
+        let e = Config::new(&a, "insignia").unwrap().exec_to(&mut out).unwrap_err();
+        assert_eq!(e.error_code, 7);
+
+        // Every file that was written counts as modified, and every other one as skipped
+        let written = files.iter().filter(|f| **f != bad && fs::read(f).unwrap() != mp3()).count();
+        let summary = String::from_utf8(out).unwrap();
+        let summary = summary.lines().last().unwrap();
+
+        assert!(summary.contains(&format!("\"processed\":{},\"modified\":{},", written + 1, written)), "{}", summary);
+        assert!(summary.contains(&format!("\"skipped\":{},\"errored\":1,", 8 - written)), "{}", summary);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bare_jobs_uses_the_cpus() {
+        let config = Config::new_in_memory(&args(&["--jobs"]), "insignia").unwrap();
+        assert_eq!(config.jobs, default_jobs());
+        assert!((1..=MAX_DEFAULT_JOBS).contains(&config.jobs));
+
+        let config = Config::new_in_memory(&args(&["--jobs=3"]), "insignia").unwrap();
+        assert_eq!(config.jobs, 3);
+
+        let config = Config::new_in_memory(&args(&[]), "insignia").unwrap();
+        assert_eq!(config.jobs, 1);
+
+        let e = Config::new_in_memory(&args(&["--jobs=0"]), "insignia").err().unwrap();
+        assert_eq!(e.error_code, 3);
+    }
+
+    #[test]
+    fn bare_jobs_leaves_the_files_alone() {
+        let dir = temp_dir("bare-jobs");
+        let files: Vec<String> = ["a.mp3", "b.mp3"].iter().map(|f| dir.join(f).to_string_lossy().into_owned()).collect();
+
+        for f in &files {
+            fs::write(f, mp3()).unwrap();
+        }
+
+        let config = Config::new(&args(&["--jobs", &files[0], &files[1]]), "insignia").unwrap();
+
+        assert_eq!(config.jobs, default_jobs());
+        assert_eq!(config.files, files);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn continue_on_error_is_keep_going() {
+        for flag in &["--keep-going", "-k", "--continue-on-error"] {
+            let config = Config::new_in_memory(&args(&[flag]), "insignia").unwrap();
+            assert!(config.keep_going, "{}", flag);
+        }
+
+        let config = Config::new_in_memory(&args(&[]), "insignia").unwrap();
+        assert!(!config.keep_going);
+    }
+
+    #[test]
+    fn piped_image_goes_on_every_file() {
+        let dir = temp_dir("piped-image");
+        let files: Vec<String> = ["a.mp3", "b.mp3"].iter().map(|f| dir.join(f).to_string_lossy().into_owned()).collect();
+
+        for f in &files {
+            fs::write(f, mp3()).unwrap();
+        }
+
+        let image = b"\x89PNG\r\n\x1a\n not really".to_vec();
+        let mut config = Config::new(&args(&["--quiet", "--image=-", &files[0], &files[1]]), "insignia").unwrap();
+        config.fill_stdin(image.clone());
+        config.exec_to(&mut Vec::new()).unwrap();
+
+        for f in &files {
+            let tag = inner::read(f).unwrap();
+            assert_eq!(tag.album_cover().map(|p| p.data.to_vec()), Some(image.clone()), "{}", f);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keep_going_with_some_failures() {
+        let dir = temp_dir("keep-going");
+        let good = dir.join("good.mp3").to_string_lossy().into_owned();
+        let bad = dir.join("bad.mp3").to_string_lossy().into_owned();
+        let worse = dir.join("worse.mp3").to_string_lossy().into_owned();
+
+        fs::write(&good, mp3()).unwrap();
+        fs::write(&bad, b"not a music file").unwrap();
+        fs::write(&worse, b"not a music file either").unwrap();
+
+        // Some files failing is a partial failure, and the ones that didn't are still edited
+        let config = Config::new(&args(&["--keep-going", "--quiet", "--title=Hello", &bad, &good]), "insignia").unwrap();
+        assert_eq!(config.exec_to(&mut Vec::new()).unwrap_err().error_code, 8);
+        assert_eq!(inner::read(&good).unwrap().title(), Some("Hello"));
+
+        // Every file failing keeps their own error code
+        let config = Config::new(&args(&["--keep-going", "--quiet", "--title=Hello", &bad, &worse]), "insignia").unwrap();
+        assert_eq!(config.exec_to(&mut Vec::new()).unwrap_err().error_code, 7);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn find_preset_takes_only_its_own_lines() {
+        let contents = "# Presets\n[podcast]\n--genre=Podcast\n\n# Shows are albums\n--album=Show\n[other]\n--year=2001\n";
+
+        assert_eq!(find_preset(contents, "podcast"), Some(vec!["--genre=Podcast".to_string(), "--album=Show".to_string()]));
+        assert_eq!(find_preset(contents, "other"), Some(vec!["--year=2001".to_string()]));
+        assert_eq!(find_preset(contents, "missing"), None);
+        assert_eq!(find_preset("[empty]\n", "empty"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn preset_expands_into_commands() {
+        let dir = temp_dir("preset");
+        let presets = dir.join("presets");
+        fs::write(&presets, "[podcast]\n--genre=Podcast\n--album=Show\n--title=Episode\n").unwrap();
+        let preset_file = format!("--preset-file={}", presets.display());
+
+        // The title given explicitly wins over the preset's
+        let (data, _) = run_bytes(&["--preset=podcast", &preset_file, "--title=Pilot"], "song.mp3", &mp3()).unwrap();
+        let (_, out) = run_bytes(&["--title", "--album", "--genre"], "song.mp3", &data).unwrap();
+        assert_eq!(out, "Pilot\nShow\nPodcast\n");
+
+        let e = run_bytes(&["--preset=missing", &preset_file], "song.mp3", &mp3()).unwrap_err();
+        assert_eq!(e.error_code, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn year_range_parses() {
+        let mut opts = Options::new();
+        opts.optopt("", "year-range", "", "MIN:MAX");
+        opts.optflag("", "no-year-range", "");
+        let range = |a: &[&str]| year_range(&opts.parse(args(a)).unwrap(), "insignia", &opts).map_err(|e| e.error_code);
+
+        assert_eq!(range(&["--year-range=1950:1999"]), Ok(Some((1950, 1999))));
+        assert_eq!(range(&["--year-range= 1950 : 1950 "]), Ok(Some((1950, 1950))));
+        assert_eq!(range(&["--no-year-range"]), Ok(None));
+        assert_eq!(range(&["--year-range=1999:1950"]), Err(3));
+        assert_eq!(range(&["--year-range=1950"]), Err(3));
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert_eq!(range(&[]), Ok(Some((1900, year_of(now) + 1))));
+    }
+
+    #[test]
+    fn year_of_lands_on_new_year() {
+        assert_eq!(year_of(0), 1970);
+        assert_eq!(year_of(951_782_400), 2000); // 2000-02-29
+        assert_eq!(year_of(1_640_995_199), 2021); // 2021-12-31 23:59:59
+        assert_eq!(year_of(1_704_067_199), 2023); // 2023-12-31 23:59:59
+        assert_eq!(year_of(1_704_067_200), 2024); // 2024-01-01 00:00:00
+        assert_eq!(year_of(1_735_603_200), 2024); // 2024-12-31, a leap year's last day
+        assert_eq!(year_of(4_107_542_400), 2100); // 2100-03-01, after a skipped leap day
+    }
+
+    #[test]
+    fn years_in_and_out_of_range() {
+        let year = |a: &[&str]| Config::new_in_memory(&args(a), "insignia").err().map(|e| e.error_code);
+
+        assert_eq!(year(&["--year=1900"]), None);
+        assert_eq!(year(&["--year=2001"]), None);
+        assert_eq!(year(&["--year=1899"]), Some(3));
+        assert_eq!(year(&["--year=20203"]), Some(3));
+
+        assert_eq!(year(&["--year-range=1990:1999", "--year=1995"]), None);
+        assert_eq!(year(&["--year-range=1990:1999", "--year=2001"]), Some(3));
+        assert_eq!(year(&["--no-year-range", "--year=20203"]), None);
+    }
+
+    #[test]
+    fn track_of_total() {
+        let opts = Options::new();
+        let number = |s: &str| parse_number_of(s, "track", "insignia", &opts).map_err(|e| e.error_code);
+
+        assert!(matches!(number("3/12"), Ok(Data::IntOf(3, 12))));
+        assert!(matches!(number(" 3 / 12 "), Ok(Data::IntOf(3, 12))));
+        assert!(matches!(number("3"), Ok(Data::Int(3))));
+        assert!(matches!(number("13/12"), Err(3)));
+        assert!(matches!(number("0/12"), Err(3)));
+        assert!(matches!(number("3/"), Err(3)));
+        assert!(matches!(number("three"), Err(3)));
+
+        let dir = temp_dir("track-of-total");
+        let path = dir.join("song.mp3");
+        let (data, _) = run_bytes(&["--track=3/12", "--disc=1/2"], "song.mp3", &mp3()).unwrap();
+        fs::write(&path, data).unwrap();
+
+        let tag = inner::read(&path.to_string_lossy()).unwrap();
+        assert_eq!(tag.track(), (Some(3), Some(12)));
+        assert_eq!(tag.disc(), (Some(1), Some(2)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn feat_words() {
+        for word in &["feat", "feat.", "Feat.", "ft", "FT.", "featuring", "(feat.", "[ft"] {
+            assert!(is_feat(word), "{}", word);
+        }
+
+        for word in &["feature", "f.", "(with", "left"] {
+            assert!(!is_feat(word), "{}", word);
+        }
+
+        assert_eq!(normalize_feat("Song (ft. Someone)", "feat."), "Song (feat. Someone)");
+        assert_eq!(normalize_feat("Band featuring Other", "ft."), "Band ft. Other");
+        assert_eq!(normalize_feat("Left Behind", "feat."), "Left Behind");
+    }
+
+    #[test]
+    fn split_feat_takes_out_the_featured_artists() {
+        let pair = |a: &str, b: &str| Some((a.to_string(), b.to_string()));
+
+        assert_eq!(split_feat("Song (feat. Someone) [Live]"), pair("Song [Live]", "Someone"));
+        assert_eq!(split_feat("Song [ft. A & B]"), pair("Song", "A & B"));
+        assert_eq!(split_feat("Song feat. Someone"), pair("Song", "Someone"));
+        assert_eq!(split_feat("Song (feat. Someone"), pair("Song", "Someone"));
+        assert_eq!(split_feat("Song"), None);
+        assert_eq!(split_feat("Song (feat.)"), None);
+    }
+
+    #[test]
+    fn path_pattern_parses() {
+        let levels = parse_path_pattern("{artist}/{album}/{track} - {title}").unwrap();
+
+        assert_eq!(levels.len(), 3);
+        assert!(matches!(levels[0].as_slice(), [PathToken::Field(Field::Artist)]));
+        assert!(matches!(levels[1].as_slice(), [PathToken::Field(Field::Album)]));
+        assert!(matches!(
+            levels[2].as_slice(),
+            [PathToken::Field(Field::Track), PathToken::Text(t), PathToken::Field(Field::Title)] if t == " - "
+        ));
+
+        let code = |p: &str| parse_path_pattern(p).err().map(|(_, code)| code);
+
+        assert_eq!(code("/{artist}//{title}/"), None);
+        assert_eq!(code(""), Some(1));
+        assert_eq!(code("{artist"), Some(1));
+        assert_eq!(code("{track}{title}"), Some(1));
+        assert_eq!(code("{image}"), Some(1));
+        assert_eq!(code("{mood}"), Some(4));
+    }
+
+    #[test]
+    fn path_pattern_matches() {
+        let levels = parse_path_pattern(DEFAULT_PATH_PATTERN).unwrap();
+        let values = |f: &str| {
+            match_path(f, &levels).map(|v| v.into_iter().map(|(field, value)| (field_to_str(field), value)).collect::<Vec<_>>())
+        };
+
+        assert_eq!(values("Music/Band/Record/03 - Tune - Live.mp3"), Some(vec![
+            ("artist", "Band".to_string()),
+            ("album", "Record".to_string()),
+            ("track", "03".to_string()),
+            ("title", "Tune - Live".to_string()),
+        ]));
+        assert_eq!(values("Band/Record/Tune.mp3"), None);
+        assert_eq!(values("Record/03 - Tune.mp3"), None);
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("Hello"), "\"Hello\"");
+        assert_eq!(json_string(r#"Say "hi"\"#), r#""Say \"hi\"\\""#);
+        assert_eq!(json_string("a\nb\tc\r"), r#""a\nb\tc\r""#);
+        assert_eq!(json_string("\u{0}\u{1f}"), r#""\u0000\u001f""#);
+        assert_eq!(json_string("Café ♫"), "\"Café ♫\"");
+    }
+
+    #[test]
+    fn base64_pads() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(&[0xFF, 0xFE, 0xFD, 0xFC]), "//79/A==");
+    }
+
+    #[test]
+    fn stray_characters() {
+        assert_eq!(stray_kinds("Hello"), Vec::<&str>::new());
+        assert_eq!(stray_kinds("Line\tone\r\nLine two"), Vec::<&str>::new());
+        assert_eq!(stray_kinds("\u{FEFF}Hello"), ["byte order mark"]);
+        assert_eq!(stray_kinds("Hello\0"), ["null byte"]);
+        assert_eq!(stray_kinds("Hel\u{7}lo"), ["control character"]);
+        assert_eq!(stray_kinds("\u{FFFE}Hel\0lo\u{1b}"), ["byte order mark", "null byte", "control character"]);
+    }
+
+    #[test]
+    fn dry_run_json_previews_the_changes() {
+        let (tagged, _) = run_bytes(&["--title=Old", "--artist=Someone"], "song.mp3", &mp3()).unwrap();
+        let (data, out) = run_bytes(&["--dry-run", "--json", "--title=New", "--year=2002", "--artist=Someone"], "song.mp3", &tagged).unwrap();
+
+        assert_eq!(data, tagged);
+        assert_eq!(out, concat!(
+            r#"{"path":"song.mp3","would_write":true,"changes":["#,
+            r#"{"field":"title","old":"Old","new":"New"},"#,
+            r#"{"field":"year","old":null,"new":2002}"#,
+            "]}\n",
+        ));
+
+        let (_, out) = run_bytes(&["--dry-run", "--json", "--title=Old"], "song.mp3", &tagged).unwrap();
+        assert_eq!(out, "{\"path\":\"song.mp3\",\"would_write\":true,\"changes\":[]}\n");
+
+        // Without anything to change, the tag is printed as usual
+        let (_, out) = run_bytes(&["--dry-run", "--json", "--only=title"], "song.mp3", &tagged).unwrap();
+        assert_eq!(out, "{\"path\":\"song.mp3\",\"title\":\"Old\"}\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recursive_walk_and_symlinked_directories() {
+        use std::os::unix::fs::symlink;
+
+        let root = temp_dir("symlinks");
+        let dir = root.join("music");
+        let other = root.join("other");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+
+        fs::write(dir.join("a.mp3"), mp3()).unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("sub").join("b.mp3"), mp3()).unwrap();
+        fs::write(other.join("c.mp3"), mp3()).unwrap();
+        symlink(&other, dir.join("linked")).unwrap();
+        symlink(&dir, dir.join("sub").join("back")).unwrap();
+
+        let names = |follow: bool| -> Vec<String> {
+            audio_files_in(&dir, follow).iter()
+                .map(|f| Path::new(f).strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(names(false), ["a.mp3", "sub/b.mp3"]);
+
+        // The link back up to the top doesn't walk it again
+        assert_eq!(names(true), ["a.mp3", "linked/c.mp3", "sub/b.mp3"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn audio_properties() {
+        let dir = temp_dir("properties");
+        let read = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            properties::read(&path.to_string_lossy()).unwrap()
+        };
+
+        let mp3 = read("song.mp3", &mp3());
+        assert_eq!((mp3.bitrate, mp3.sample_rate, mp3.channels, mp3.tag_size), (Some(128), Some(44100), Some(2), Some(10)));
+        assert_eq!(mp3.duration.map(|d| d.as_millis()), Some(522));
+
+        // A byte rate too big to be real leaves the bitrate out rather than overflowing
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x02\0\x44\xAC\0\0".to_vec();
+        wav.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x04, 0, 0x10, 0]);
+        let wav = read("song.wav", &wav);
+        assert_eq!((wav.bitrate, wav.sample_rate, wav.channels), (None, Some(44100), Some(2)));
+
+        let unknown = read("song.xyz", b"not a music file");
+        assert_eq!((unknown.duration, unknown.bitrate, unknown.sample_rate, unknown.channels), (None, None, None, None));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn warnings_go_to_the_given_writer() {
+        let dir = temp_dir("warnings");
+        let files = write_files(&dir, &["good.mp3"], &mp3());
+        let bad = write_files(&dir, &["bad.mp3"], b"not a music file");
+
+        let (result, out, warnings) = run(&["--keep-going", "--quiet", "--title=Hello", &bad[0], &files[0]]);
+
+        assert_eq!(result.unwrap_err().error_code, 8);
+        assert_eq!(out, "");
+        assert_eq!(warnings, format!("Failure to open `{}` for editing\n", bad[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn warns_about_a_field_that_was_dropped() {
+        let dir = temp_dir("dropped");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        let warnings = Shared::default();
+        let mut config = Config::new(&args(&["--title=Hello", &files[0]]), "insignia").ok().unwrap();
+        config.warnings_to(warnings.clone());
+
+        // A title that's set but never saved stands in for a format that can't hold it
+        let mut tag = inner::read(&files[0]).unwrap();
+        tag.set_title("Hello");
+        config.warn_dropped_fields(&files[0], &files[0], &mut *tag);
+
+        assert_eq!(warnings.text(), format!("Warning: 'title' wasn't saved to `{}`, its tag format doesn't support the field\n", files[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_dropped_cover_when_there_was_none_to_add() {
+        let dir = temp_dir("no-cover");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+
+        let (result, out, warnings) = run(&["--auto-cover", "--title=Hello", &files[0]]);
+
+        assert!(result.is_ok());
+        assert!(out.contains("Title: Hello\n"));
+        assert_eq!(warnings, format!("`{}` has no cover and there's no cover file next to it\n", files[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transaction_leaves_every_file_alone_on_a_failure() {
+        for jobs in &["--jobs=1", "--jobs=2"] {
+            for keep_going in &[false, true] {
+                let dir = temp_dir("transaction");
+                let mut files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+                files.extend(write_files(&dir, &["bad.mp3"], b"not a music file"));
+                files.extend(write_files(&dir, &["c.mp3", "d.mp3"], &mp3()));
+
+                let mut a = vec!["--transaction", "--quiet", "--title=Hello", jobs];
+                if *keep_going {
+                    a.push("--keep-going");
+                }
+                a.extend(files.iter().map(String::as_str));
+
+                let (result, _, warnings) = run(&a);
+                assert!(result.is_err());
+                assert!(warnings.contains("No files were changed"), "{}", warnings);
+
+                for f in &files {
+                    let expected: &[u8] = if f.ends_with("bad.mp3") { b"not a music file" } else { &mp3() };
+                    assert_eq!(fs::read(f).unwrap(), expected, "{} with {}", f, jobs);
+                }
+
+                // Only the originals are left, without any of the copies they were edited as
+                let mut left: Vec<String> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+                left.sort();
+                assert_eq!(left, ["a.mp3", "b.mp3", "bad.mp3", "c.mp3", "d.mp3"]);
+
+                fs::remove_dir_all(&dir).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn transaction_writes_every_file_on_success() {
+        let dir = temp_dir("transaction-success");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+
+        let (result, _, _) = run(&["--transaction", "--quiet", "--title=Hello", &files[0], &files[1]]);
+        result.unwrap();
+
+        for f in &files {
+            assert_eq!(inner::read(f).unwrap().title(), Some("Hello"));
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watch_tags_new_files_once_they_settle() {
+        let dir = temp_dir("watch");
+        let old = write_files(&dir, &["old.mp3"], &mp3());
+        let watched = dir.to_string_lossy().into_owned();
+
+        let mut config = Config::new(&args(&["--watch", &watched, "--quiet", "--title=Hello"]), "insignia").unwrap();
+        config.warnings_to(io::sink());
+
+        let mut seen: HashSet<PathBuf> = config.watched_files(&watched).unwrap().into_iter().collect();
+        let mut pending = HashMap::new();
+        let poll = |seen: &mut HashSet<PathBuf>, pending: &mut HashMap<_, _>| config.poll_once(&watched, seen, pending, &mut Vec::new()).unwrap();
+
+        // Still being written: its size changes between polls
+        let new = dir.join("new.mp3");
+        let data = mp3();
+        fs::write(&new, &data[..100]).unwrap();
+        assert!(poll(&mut seen, &mut pending).is_empty());
+        fs::write(&new, &data).unwrap();
+        assert!(poll(&mut seen, &mut pending).is_empty());
+        assert_eq!(fs::read(&new).unwrap(), data);
+
+        // Settled
+        let new = new.to_string_lossy().into_owned();
+        assert_eq!(poll(&mut seen, &mut pending), vec![new.clone()]);
+        assert_eq!(inner::read(&new).unwrap().title(), Some("Hello"));
+
+        // Files are only tagged once, and the ones there from the start never are
+        assert!(poll(&mut seen, &mut pending).is_empty());
+        assert_eq!(fs::read(&old[0]).unwrap(), mp3());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stats_over_a_set_of_files() {
+        let dir = temp_dir("stats");
+        let image = write_files(&dir, &["cover.png"], b"\x89PNG\r\n\x1a\n not really");
+        let (a, _) = run_bytes(&["--album=One", "--artist=Someone", &format!("--image={}", image[0])], "a.mp3", &mp3()).unwrap();
+        let (b, _) = run_bytes(&["--album=One", "--artist=Someone Else"], "b.mp3", &mp3()).unwrap();
+        let (c, _) = run_bytes(&["--album=Two", "--artist=Someone"], "c.mp3", &mp3()).unwrap();
+
+        let mut files = write_files(&dir, &["a.mp3"], &a);
+        files.extend(write_files(&dir, &["b.mp3"], &b));
+        files.extend(write_files(&dir, &["c.mp3"], &c));
+        let size: u64 = files.iter().map(|f| fs::metadata(f).unwrap().len()).sum();
+
+        let (result, out, _) = run(&["--stats", &files[0], &files[1], &files[2]]);
+        result.unwrap();
+        assert_eq!(out, format!("Files: 3\nTotal Size: {} bytes\nMissing Image: 2\nAlbums: 2\nArtists: 2\nAverage Length: 0:01\n", size));
+
+        let (result, out, _) = run(&["--stats", "--json", &files[0], &files[1], &files[2]]);
+        result.unwrap();
+        assert_eq!(out, format!(
+            "{{\"files\":3,\"total_size\":{},\"missing_image\":2,\"albums\":2,\"artists\":2,\"average_length\":0.522}}\n",
+            size,
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "analysis")]
+    #[test]
+    fn halt_timeout_gives_up_on_slow_analysis() {
+        fn slow(_: &str) -> Option<analysis::Loudness> {
+            thread::sleep(Duration::from_secs(2));
+            None
+        }
+
+        fn quiet(_: &str) -> Option<analysis::Loudness> {
+            None
+        }
+
+        let config = Config::new_in_memory(&args(&["--halt-timeout=0.05"]), "insignia").unwrap();
+        let started = std::time::Instant::now();
+
+        assert_eq!(config.analyze("song.wav", slow).err().unwrap().error_code, 11);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // Analysis that finishes in time fails or succeeds on its own terms
+        assert_eq!(config.analyze("song.wav", quiet).err().unwrap().error_code, 7);
+
+        let e = Config::new_in_memory(&args(&["--halt-timeout=0"]), "insignia").err().unwrap();
+        assert_eq!(e.error_code, 3);
+    }
+
+    /// A 16-bit WAV file of a 1kHz sine at 48kHz, `amplitude` of full scale, in every channel
+    #[cfg(feature = "analysis")]
+    fn wav(channels: u16, amplitude: f64, secs: u32) -> Vec<u8> {
+        let rate: u32 = 48000;
+        let frames = rate * secs;
+        let size = frames * u32::from(channels) * 2;
+
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(36 + size).to_le_bytes());
+        data.extend_from_slice(b"WAVEfmt ");
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&channels.to_le_bytes());
+        data.extend_from_slice(&rate.to_le_bytes());
+        data.extend_from_slice(&(rate * u32::from(channels) * 2).to_le_bytes());
+        data.extend_from_slice(&(channels * 2).to_le_bytes());
+        data.extend_from_slice(&16u16.to_le_bytes());
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&size.to_le_bytes());
+
+        for i in 0..frames {
+            let sample = (amplitude * 32767.0 * (2.0 * std::f64::consts::PI * 1000.0 * f64::from(i) / f64::from(rate)).sin()) as i16;
+            for _ in 0..channels {
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+
+        data
+    }
+
+    #[cfg(feature = "analysis")]
+    #[test]
+    fn replaygain_of_mono_and_stereo() {
+        let dir = temp_dir("replaygain");
+        let files = [
+            write_files(&dir, &["mono.wav"], &wav(1, 0.5, 3)),
+            write_files(&dir, &["stereo.wav"], &wav(2, 0.5, 3)),
+            write_files(&dir, &["quiet.wav"], &wav(2, 0.25, 3)),
+        ];
+        let gain = |f: &[String]| analysis::analyze(&f[0]).unwrap().gain().unwrap();
+
+        // A 1kHz sine at full scale is -3.01 LUFS in one channel, and twice as loud in two, which
+        // ReplayGain 2.0 brings to -18 LUFS
+        assert!((gain(&files[0]) - -8.97).abs() < 0.1, "{}", gain(&files[0]));
+        assert!((gain(&files[1]) - -11.98).abs() < 0.1, "{}", gain(&files[1]));
+        assert!((gain(&files[2]) - -5.96).abs() < 0.1, "{}", gain(&files[2]));
+
+        let peak = analysis::analyze(&files[1][0]).unwrap().peak;
+        assert!((peak - 0.5).abs() < 0.001, "{}", peak);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "analysis")]
+    #[test]
+    fn replaygain_album() {
+        let dir = temp_dir("replaygain-album");
+        let mut files = write_files(&dir, &["loud.wav"], &wav(2, 0.5, 3));
+        files.extend(write_files(&dir, &["quiet.wav"], &wav(2, 0.25, 3)));
+
+        let tracks: Vec<analysis::Loudness> = files.iter().map(|f| analysis::analyze(f).unwrap()).collect();
+        let album = analysis::Loudness::album(&tracks);
+
+        // The album's loudness is the mean of the two tracks' power, not their loudness
+        let expected = -11.98 + 10.0 * (2.0f64 / 1.25).log10();
+        assert!((album.gain().unwrap() - expected).abs() < 0.1, "{}", album.gain().unwrap());
+        assert!((album.peak - 0.5).abs() < 0.001);
+
+        let (result, out, _) = run(&["--apply-replaygain", &files[0], &files[1]]);
+        result.unwrap();
+        assert_eq!(out, format!(
+            "{}: track gain -11.99 dB, peak 0.499969\n{}: track gain -5.96 dB, peak 0.249969\nAlbum: gain -9.94 dB, peak 0.499969\n",
+            files[0], files[1],
+        ));
+
+        // WAV files without any tags get an ID3v2 chunk to hold the values
+        for f in &files {
+            let mut tag = inner::read(f).unwrap();
+            assert_eq!(inner::raw(tag.as_mut(), "REPLAYGAIN_ALBUM_GAIN").unwrap(), Some("-9.94 dB".to_string()));
+        }
+        assert!(analysis::analyze(&files[0]).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn roundtrip_test_reports_nothing_lost_from_a_full_tag() {
+        let dir = temp_dir("roundtrip");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_title("Tune");
+            t.set_artist("Band");
+            t.set_album("Record");
+            t.set_year(1999);
+            t.set_track(3);
+            t.set_total_tracks(12);
+            t.set_genre("Rock");
+            t.add_comment(id3::frame::Comment { lang: "eng".to_string(), description: String::new(), text: "Loud".to_string() });
+            t.add_extended_text("MOOD", "Happy");
+            t.add_picture(front_cover(cover().data));
+        });
+        let original = fs::read(&files[0]).unwrap();
+
+        let (result, out, _) = run(&["--roundtrip-test", &files[0]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}: OK\n", files[0]));
+        assert_eq!(fs::read(&files[0]).unwrap(), original);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_read_after_write_skips_the_printout() {
+        let dir = temp_dir("no-read-after-write");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+
+        let (result, out, warnings) = run(&["--no-read-after-write", "--title=Hello", &files[0]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        assert_eq!(warnings, "");
+        assert_eq!(inner::read(&files[0]).unwrap().title(), Some("Hello"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn guard_audio_checksum_puts_back_a_file_whose_audio_changed() {
+        let dir = temp_dir("guard-audio");
+
+        // The RIFF size stops short of the data chunk, so the ID3 chunk is written in front of it,
+        // with a size that runs into the audio
+        let mut wav = b"RIFF\x1c\x00\x00\x00WAVE".to_vec();
+        wav.extend_from_slice(b"fmt \x10\x00\x00\x00\x01\x00\x01\x00\x44\xac\x00\x00\x88\x58\x01\x00\x02\x00\x10\x00");
+        wav.extend_from_slice(b"data\x08\x00\x00\x00\x01\x02\x03\x04\x05\x06\x07\x08");
+        let files = write_files(&dir, &["song.wav"], &wav);
+
+        let (result, out, _) = run(&["--guard-audio-checksum", "--title=Hello", &files[0]]);
+
+        let err = result.unwrap_err();
+        assert_eq!(err.error_code, 9);
+        assert_eq!(err.error_str, format!("Writing the tag of `{}` changed its audio, so the file was put back the way it was", files[0]));
+        assert_eq!(out, "");
+        assert_eq!(fs::read(&files[0]).unwrap(), wav);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn confirm_needs_yes_without_a_terminal() {
+        let dir = temp_dir("confirm");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        let warnings = Shared::default();
+        let mut config = Config::new(&args(&["--confirm", "--title=Hello", &files[0]]), "insignia").ok().unwrap();
+        config.warnings_to(warnings.clone());
+
+        // Nothing is asked when there's no one to answer
+        assert_eq!(config.ask_to_confirm(None).unwrap_err().error_code, 10);
+        assert_eq!(warnings.text(), "About to set title in 1 file(s)\n");
+
+        assert_eq!(config.ask_to_confirm(Some(&mut &b"n\n"[..])).unwrap_err().error_code, 10);
+        assert!(config.ask_to_confirm(Some(&mut &b"y\n"[..])).is_ok());
+
+        let config = Config::new(&args(&["--confirm", "--yes", "--title=Hello", &files[0]]), "insignia").ok().unwrap();
+        assert!(config.ask_to_confirm(None).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_image_rejects_a_cut_off_image() {
+        let dir = temp_dir("validate-image");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        let full = png(16, 16);
+        let cut = write_files(&dir, &["cut.png"], &full[..full.len() / 2]);
+        let whole = write_files(&dir, &["whole.png"], &full);
+        let image = |p: &str| format!("--image={}", p);
+
+        let (result, _, _) = run(&["--validate-image", &image(&cut[0]), &files[0]]);
+        let err = result.unwrap_err();
+        assert_eq!(err.error_code, 2);
+        assert_eq!(err.error_str, "The image couldn't be decoded, it may be corrupt or truncated");
+        assert_eq!(fs::read(&files[0]).unwrap(), mp3());
+
+        // Without the check, only the start of the image is looked at
+        assert!(run(&["--quiet", &image(&cut[0]), &files[0]]).0.is_ok());
+
+        assert!(run(&["--quiet", "--validate-image", &image(&whole[0]), &files[0]]).0.is_ok());
+        assert_eq!(inner::read(&files[0]).unwrap().album_cover().unwrap().data, &full[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tag_size_limit_rejects_a_bigger_tag() {
+        let dir = temp_dir("tag-size-limit");
+        let files = write_files(&dir, &["small.mp3", "big.mp3"], &mp3());
+        write_tag(&files[1], |t| t.set_title("x".repeat(200)));
+        let size = properties::read(&files[1]).unwrap().tag_size.unwrap();
+        assert!(size > 200);
+
+        assert!(run(&["--quiet", "--tag-size-limit=100", &files[0]]).0.is_ok());
+
+        let err = run(&["--quiet", "--tag-size-limit=100", &files[1]]).0.unwrap_err();
+        assert_eq!(err.error_code, 9);
+        assert_eq!(err.error_str, format!("`{}`'s tags take up {} bytes, more than the limit of 100", files[1], size));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repair_moves_an_appended_tag_to_the_start() {
+        let dir = temp_dir("repair");
+        let audio = &mp3()[10..];
+        let frame = b"TIT2\x00\x00\x00\x06\x00\x00\x03Hello";
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(128, 0);
+
+        let mut data = audio.to_vec();
+        data.extend_from_slice(b"ID3\x04\x00\x10\x00\x00\x00\x10");
+        data.extend_from_slice(frame);
+        data.extend_from_slice(b"3DI\x04\x00\x10\x00\x00\x00\x10");
+        data.extend_from_slice(&id3v1);
+        let files = write_files(&dir, &["song.mp3"], &data);
+
+        let (result, _, warnings) = run(&["--repair", "--quiet", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("Repaired `{}`: moved its ID3v2 tag from the end to the start\n", files[0]));
+
+        // The footer is gone, and so is the flag saying there's one
+        let mut repaired = b"ID3\x04\x00\x00\x00\x00\x00\x10".to_vec();
+        repaired.extend_from_slice(frame);
+        repaired.extend_from_slice(audio);
+        repaired.extend_from_slice(&id3v1);
+        assert_eq!(fs::read(&files[0]).unwrap(), repaired);
+        assert_eq!(inner::read(&files[0]).unwrap().title(), Some("Hello"));
+
+        let (result, _, warnings) = run(&["--repair", "--quiet", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("`{}` didn't need repairing\n", files[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_encoding_names_each_frames_encoding() {
+        let dir = temp_dir("print-encoding");
+        let files = write_files(&dir, &["song.mp3"], &legacy_mp3());
+
+        let (result, out, _) = run(&["--print-encoding", &files[0]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, "TIT2: Latin-1\nTPE1: UTF-16\nTALB: UTF-8\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reencode_utf8_rewrites_legacy_frames() {
+        let dir = temp_dir("reencode-utf8");
+        let files = write_files(&dir, &["song.mp3"], &legacy_mp3());
+
+        let (result, _, warnings) = run(&["--reencode-utf8", "--quiet", &files[0]]);
+
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("Re-encoding TIT2 (Latin-1), TPE1 (UTF-16) in `{}` as UTF-8\n", files[0]));
+        assert_eq!(run(&["--print-encoding", &files[0]]).1, "TIT2: UTF-8\nTPE1: UTF-8\nTALB: UTF-8\n");
+
+        let tag = inner::read(&files[0]).unwrap();
+        assert_eq!(tag.title(), Some("Caf\u{e9}"));
+        assert_eq!(tag.artist_str(), Some("Band"));
+        assert_eq!(tag.album_title(), Some("Record"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summary_json_counts_a_mixed_batch() {
+        let dir = temp_dir("summary-json");
+        let files = write_files(&dir, &["01 a.mp3", "02 b.mp3"], &mp3());
+        let bad = write_files(&dir, &["03 bad.mp3"], b"not a music file");
+        let last = write_files(&dir, &["04 d.mp3"], &mp3());
+        // Already has a track, so it's left unchanged
+        write_tag(&files[1], |t| t.set_track(2));
+        let error = format!(r#"{{"path":"{}","message":"Failure to open `{}` for editing","code":7}}"#, bad[0], bad[0]);
+
+        let (result, out, _) = run(&["--summary-json", "--keep-going", "--quiet", "--normalize-track-from-filename", &files[0], &files[1], &bad[0], &last[0]]);
+        assert_eq!(result.unwrap_err().error_code, 8);
+        assert_eq!(out, format!(r#"{{"processed":4,"modified":2,"unchanged":1,"skipped":0,"errored":1,"errors":[{}]}}"#, error) + "\n");
+
+        // Without --keep-going, the file after the failure is never reached
+        let files = write_files(&dir, &["01 a.mp3"], &mp3());
+        let (result, out, _) = run(&["--summary-json", "--quiet", "--normalize-track-from-filename", &files[0], &bad[0], &last[0]]);
+        assert_eq!(result.unwrap_err().error_code, 7);
+        assert_eq!(out, format!(r#"{{"processed":2,"modified":1,"unchanged":0,"skipped":1,"errored":1,"errors":[{}]}}"#, error) + "\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limit_takes_the_first_files() {
+        let dir = temp_dir("limit");
+        let files = write_files(&dir, &["a.mp3", "b.mp3", "c.mp3"], &mp3());
+        let chosen = |a: &[&str]| {
+            let a: Vec<&str> = a.iter().copied().chain(files.iter().map(String::as_str)).collect();
+            Config::new(&args(&a), "insignia").map(|c| c.files).map_err(|e| e.error_code)
+        };
+
+        assert_eq!(chosen(&["--limit=2"]), Ok(files[..2].to_vec()));
+        assert_eq!(chosen(&["--limit=5"]), Ok(files.clone()));
+        assert_eq!(chosen(&["--limit=-1"]), Err(3));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skip_pages_through_files_with_limit() {
+        let dir = temp_dir("skip");
+        let files = write_files(&dir, &["a.mp3", "b.mp3", "c.mp3", "d.mp3", "e.mp3"], &mp3());
+        let chosen = |a: &[&str]| {
+            let a: Vec<&str> = a.iter().copied().chain(files.iter().map(String::as_str)).collect();
+            Config::new(&args(&a), "insignia").map(|c| c.files).map_err(|e| e.error_code)
+        };
+
+        assert_eq!(chosen(&["--skip=3"]), Ok(files[3..].to_vec()));
+        assert_eq!(chosen(&["--skip=1", "--limit=2"]), Ok(files[1..3].to_vec()));
+        // Skipping comes first whichever order they're given in
+        assert_eq!(chosen(&["--limit=2", "--skip=4"]), Ok(files[4..].to_vec()));
+        assert_eq!(chosen(&["--skip=x"]), Err(3));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_genre_list_numbers_the_id3v1_genres() {
+        let err = Config::new(&args(&["--print-genre-list"]), "insignia").err().unwrap();
+        let lines: Vec<&str> = err.error_str.lines().collect();
+
+        assert_eq!(err.error_code, 0);
+        assert_eq!(lines.len(), 192);
+        assert_eq!(lines[0], "0: Blues");
+        assert_eq!(lines[17], "17: Rock");
+        assert_eq!(lines[79], "79: Hard Rock");
+        assert_eq!(lines[191], "191: Psybient");
+    }
+
+    #[test]
+    fn genre_id_sets_the_genre_it_names() {
+        let dir = temp_dir("genre-id");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        let code = |a: &[&str]| Config::new(&args(a), "insignia").err().unwrap().error_code;
+
+        assert!(run(&["--quiet", "--genre-id=17", &files[0]]).0.is_ok());
+        assert_eq!(inner::genre(&mut *inner::read(&files[0]).unwrap()).unwrap().as_deref(), Some("Rock"));
+
+        assert_eq!(code(&["--genre-id=192", &files[0]]), 3);
+        assert_eq!(code(&["--genre-id=-1", &files[0]]), 3);
+        assert_eq!(code(&["--genre-id=Rock", &files[0]]), 3);
+        assert_eq!(code(&["--genre-id=0", "--genre=Blues", &files[0]]), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn genre_validate_prints_genres_that_arent_allowed() {
+        let dir = temp_dir("genre-validate");
+        let files = write_files(&dir, &["rock.mp3", "shouty.mp3", "polka.mp3", "none.mp3"], &mp3());
+        write_tag(&files[0], |t| t.set_genre("Rock"));
+        write_tag(&files[1], |t| t.set_genre("ROCK"));
+        write_tag(&files[2], |t| t.set_genre("Polka"));
+        let list = dir.join("genres.txt");
+        fs::write(&list, "# Allowed\nRock\n\n  Jazz \n").unwrap();
+        let validate = format!("--genre-validate={}", list.display());
+
+        let (result, out, _) = run(&[&validate, &files[0], &files[1], &files[2], &files[3]]);
+        let err = result.unwrap_err();
+        assert_eq!(err.error_code, 9);
+        assert_eq!(err.error_str, "2 of 4 files have a genre that isn't allowed");
+        assert_eq!(out, format!("{}: ROCK\n{}: Polka\n", files[1], files[2]));
+
+        let (result, out, _) = run(&[&validate, "--genre-ignore-case", &files[0], &files[1], &files[2], &files[3]]);
+        assert_eq!(result.unwrap_err().error_str, "1 of 4 files have a genre that isn't allowed");
+        assert_eq!(out, format!("{}: Polka\n", files[2]));
+
+        let (result, out, _) = run(&[&validate, &files[0], &files[3]]);
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_version_of_shows_each_tags_value() {
+        let dir = temp_dir("print-version-of");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| t.set_title("New"));
+        let mut id3v1 = b"TAGOld".to_vec();
+        id3v1.resize(128, 0);
+        let mut data = fs::read(&files[0]).unwrap();
+        data.extend_from_slice(&id3v1);
+        fs::write(&files[0], &data).unwrap();
+
+        let (result, out, _) = run(&["--print-version-of=title", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}: title\n  shown: New\n  ID3v2: New\n  ID3v1: Old\n  the tags disagree\n", files[0]));
+
+        // ID3v1 has no album artist, so it's left out rather than shown as unset
+        let (_, out, _) = run(&["--print-version-of=albumartist", &files[0]]);
+        assert_eq!(out, format!("{}: albumartist\n  shown: \n  ID3v2: (not set)\n", files[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn field_order_puts_listed_fields_first() {
+        let dir = temp_dir("field-order");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_title("Tune");
+            t.set_artist("Band");
+        });
+        let code = |a: &[&str]| Config::new(&args(a), "insignia").err().unwrap().error_code;
+
+        let (result, out, _) = run(&["--field-order=artist, title", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(out, "Artist: Band\nTitle: Tune\nDisc: 0\nTrack: 0\nAlbum: \nAlbum Arist: \nGenre: \nComposer: \nComment: \nImage: No image\nYear: 0\nLength: 0:01\n\n");
+
+        let (_, out, _) = run(&["--field-order=artist,title", "--omit-unlisted", &files[0]]);
+        assert_eq!(out, "Artist: Band\nTitle: Tune\nLength: 0:01\n\n");
+
+        assert_eq!(code(&["--field-order=artist,artist", &files[0]]), 1);
+        assert_eq!(code(&["--field-order=mood", &files[0]]), 4);
+        assert_eq!(code(&["--omit-unlisted", &files[0]]), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_relative_shortens_every_path() {
+        let dir = temp_dir("print-relative");
+        fs::create_dir(dir.join("sub")).unwrap();
+        let files = write_files(&dir, &["sub/song.mp3"], &mp3());
+        let base = format!("--print-relative={}", dir.display());
+
+        let (_, out, _) = run(&[&base, "--print-path", &files[0]]);
+        assert!(out.starts_with("sub/song.mp3:\nDisc: 0\n"));
+
+        let (_, out, _) = run(&[&base, "--json", &files[0]]);
+        assert!(out.starts_with(r#"{"path":"sub/song.mp3","disc":null,"#));
+
+        let (_, out, _) = run(&[&base, "--print-field-presence", "--csv", &files[0]]);
+        assert_eq!(out.lines().nth(1), Some("sub/song.mp3,N,N,N,N,N,N,N,N,N,N,N"));
+
+        // Without it, paths are shown as they were given
+        let (_, out, _) = run(&["--print-path", &files[0]]);
+        assert!(out.starts_with(&format!("{}:\n", files[0])));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_art_info_measures_the_cover() {
+        let dir = temp_dir("print-art-info");
+        let files = write_files(&dir, &["good.mp3", "corrupt.mp3", "none.mp3"], &mp3());
+        let image = png(3, 2);
+        let size = image.len();
+        write_tag(&files[0], |t| t.add_picture(front_cover(image)));
+        write_tag(&files[1], |t| t.add_picture(front_cover(cover().data)));
+
+        let (result, out, _) = run(&["--print-art-info", &files[0], &files[1], &files[2]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, format!(
+            "{}: image/png, 3x2, {} bytes\n{}: image/png, unknown dimensions, 15 bytes\n{}: no image\n",
+            files[0], size, files[1], files[2],
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_changed_fields_names_what_changed() {
+        let dir = temp_dir("print-changed-fields");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| t.set_artist("Band"));
+
+        let (result, out, _) = run(&["--print-changed-fields", "--year=1999", "--title=Tune", "--artist=Band", "--album=Record", &files[0]]);
+
+        // The artist was already set to what it's set to, so it didn't change
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}: title, album, year\n", files[0]));
+
+        let (_, out, _) = run(&["--print-changed-fields", "--artist=Band", &files[0]]);
+        assert_eq!(out, "");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_modified_only_leaves_out_unchanged_files() {
+        let dir = temp_dir("print-modified-only");
+        let files = write_files(&dir, &["new.mp3", "same.mp3"], &mp3());
+        write_tag(&files[1], |t| t.set_title("Tune"));
+
+        let (result, out, _) = run(&["--print-modified-only", "--title=Tune", &files[0], &files[1]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}:\n  Title:  -> Tune\n", files[0]));
+
+        let (_, out, _) = run(&["--print-modified-only", "--title=Tune", &files[1]]);
+        assert_eq!(out, "");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn length_is_shown_in_the_printout_and_json() {
+        let dir = temp_dir("length");
+        // 230 frames of 1152 samples at 44.1kHz are a little over 6 seconds
+        let mut data = mp3();
+        for _ in 20..230 {
+            data.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+            data.extend_from_slice(&[0; 413]);
+        }
+        let files = write_files(&dir, &["song.mp3"], &data);
+
+        let (_, out, _) = run(&[&files[0]]);
+        assert!(out.ends_with("Year: 0\nLength: 0:06\n\n"));
+
+        let (_, out, _) = run(&["--json", &files[0]]);
+        assert!(out.contains(r#""year":null,"length":6.008,"bitrate":128,"#));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_shows_the_fields_given() {
+        let dir = temp_dir("only");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_title("Tune");
+            t.set_year(2003);
+        });
+
+        let (result, out, _) = run(&["--only=year", "--only=title", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(out, "Year: 2003\nTitle: Tune\n\n");
+
+        assert_eq!(Config::new(&args(&["--only=mood", &files[0]]), "insignia").err().unwrap().error_code, 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pad_zero_pads_track_and_disc() {
+        let dir = temp_dir("pad");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_track(3);
+            t.set_disc(1);
+        });
+
+        let (_, out, _) = run(&["--pad=3", "--only=disc", "--only=track", &files[0]]);
+        assert_eq!(out, "Disc: 001\nTrack: 003\n\n");
+
+        let (_, out, _) = run(&["--pad-track=2", "--only=disc", "--only=track", &files[0]]);
+        assert_eq!(out, "Disc: 1\nTrack: 03\n\n");
+
+        // JSON keeps numbers as numbers
+        let (_, out, _) = run(&["--pad=3", "--print-field-json=track", &files[0]]);
+        assert!(out.ends_with("\"value\":3}\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_path_heads_each_files_printout() {
+        let dir = temp_dir("print-path");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+        write_tag(&files[0], |t| t.set_title("Tune"));
+
+        let (result, out, _) = run(&["--print-path", "--only=title", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}:\nTitle: Tune\n\n{}:\nTitle: \n\n", files[0], files[1]));
+
+        // The compact printout already starts with the path
+        let (_, out, _) = run(&["--print-path", "--compact", &files[0]]);
+        assert_eq!(out, format!("{}: Tune\n", files[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compact_shows_each_file_on_one_line() {
+        let dir = temp_dir("compact");
+        let files = write_files(&dir, &["full.mp3", "no-album.mp3", "year-only.mp3", "empty.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_artist("Band");
+            t.set_title("Tune");
+            t.set_album("Record");
+            t.set_year(2003);
+        });
+        write_tag(&files[1], |t| t.set_title("Tune"));
+        write_tag(&files[2], |t| t.set_year(2003));
+
+        let (result, out, _) = run(&["--compact", &files[0], &files[1], &files[2], &files[3]]);
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}: Band - Tune [Record, 2003]\n{}: Tune\n{}: [2003]\n{}:\n", files[0], files[1], files[2], files[3]));
+
+        let (_, out, _) = run(&["--compact", "--only=year", "--only=artist", &files[0]]);
+        assert_eq!(out, format!("{}: 2003 - Band\n", files[0]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_field_json_prints_a_line_per_file() {
+        let dir = temp_dir("print-field-json");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_title("Say \"hi\"");
+            t.set_year(2003);
+        });
+
+        let (result, out, _) = run(&["--print-field-json=title", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(out, format!(
+            concat!(r#"{{"path":"{}","field":"title","value":"Say \"hi\""}}"#, "\n", r#"{{"path":"{}","field":"title","value":null}}"#, "\n"),
+            files[0], files[1],
+        ));
+
+        let (_, out, _) = run(&["--print-field-json=year", &files[0]]);
+        assert_eq!(out, format!(r#"{{"path":"{}","field":"year","value":2003}}"#, files[0]) + "\n");
+
+        assert_eq!(Config::new(&args(&["--print-field-json=mood", &files[0]]), "insignia").err().unwrap().error_code, 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_tag_count_marks_repeated_frames() {
+        let dir = temp_dir("print-tag-count");
+        // The id3 crate would merge the two artist frames into one
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x3c".to_vec();
+        data.extend_from_slice(b"TIT2\x00\x00\x00\x05\x00\x00\x03Tune");
+        data.extend_from_slice(b"TPE1\x00\x00\x00\x02\x00\x00\x03A");
+        data.extend_from_slice(b"TPE1\x00\x00\x00\x02\x00\x00\x03B");
+        data.extend_from_slice(b"TXXX\x00\x00\x00\x0b\x00\x00\x03MOOD\x00Happy");
+        data.extend_from_slice(&mp3()[10..]);
+        let files = write_files(&dir, &["song.mp3"], &data);
+
+        let (result, out, _) = run(&["--print-tag-count", &files[0]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, "Frames: 4\n  TIT2: 1\n  TPE1: 2 (duplicated)\n  TXXX:MOOD: 1\n\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_image_all_names_images_after_their_files() {
+        let dir = temp_dir("export-image-all");
+        for sub in &["one", "two", "out"] {
+            fs::create_dir(dir.join(sub)).unwrap();
+        }
+        let files = write_files(&dir, &["one/song.mp3", "one/none.mp3", "two/song.mp3"], &mp3());
+        write_tag(&files[0], |t| t.add_picture(front_cover(png(1, 1))));
+        fs::copy(&files[0], &files[2]).unwrap();
+        let export = format!("--export-image-all={}", dir.join("out").display());
+
+        let (result, _, warnings) = run(&[&export, &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("`{}` has no image, skipping\n", files[1]));
+        assert_eq!(fs::read(dir.join("out/song.png")).unwrap(), png(1, 1));
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 1);
+
+        // The other song.mp3 would write over the first one's image
+        let err = run(&[&export, &files[2]]).0.unwrap_err();
+        assert_eq!(err.error_code, 2);
+        assert_eq!(err.error_str, format!("{} already exists, not replacing it without --force", dir.join("out/song.png").display()));
+        assert!(run(&[&export, "--force", &files[2]]).0.is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_cover_to_sidecar_keeps_other_files() {
+        let dir = temp_dir("sidecar");
+        fs::create_dir(dir.join("album")).unwrap();
+        let files = write_files(&dir, &["album/a.mp3", "album/b.mp3"], &mp3());
+        for f in &files {
+            write_tag(f, |t| t.add_picture(front_cover(png(1, 1))));
+        }
+
+        // Every track writes the same cover, which is fine
+        let (result, _, warnings) = run(&["--write-cover-to-sidecar", "--quiet", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, "");
+        assert_eq!(fs::read(dir.join("album/folder.png")).unwrap(), png(1, 1));
+
+        assert!(run(&["--write-cover-to-sidecar", "--sidecar-name=cover.jpg", "--quiet", &files[0]]).0.is_ok());
+        assert_eq!(fs::read(dir.join("album/cover.jpg")).unwrap(), png(1, 1));
+
+        // A different image isn't replaced without --force
+        fs::write(dir.join("album/folder.png"), b"another image").unwrap();
+        let (result, _, warnings) = run(&["--write-cover-to-sidecar", "--quiet", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("`{}` already exists and isn't `{}`'s cover, not replacing it without --force\n", dir.join("album/folder.png").display(), files[0]));
+        assert_eq!(fs::read(dir.join("album/folder.png")).unwrap(), b"another image");
+
+        assert!(run(&["--write-cover-to-sidecar", "--force", "--quiet", &files[0]]).0.is_ok());
+        assert_eq!(fs::read(dir.join("album/folder.png")).unwrap(), png(1, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dump_image_writes_next_to_each_file() {
+        let dir = temp_dir("dump-image");
+        let files = write_files(&dir, &["song.mp3", "none.mp3"], &mp3());
+        write_tag(&files[0], |t| t.add_picture(front_cover(png(1, 1))));
+
+        let (result, _, warnings) = run(&["--dump-image", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("`{}` has no image, skipping\n", files[1]));
+        assert_eq!(fs::read(dir.join("song.png")).unwrap(), png(1, 1));
+
+        let err = run(&["--dump-image", &files[0]]).0.unwrap_err();
+        assert_eq!(err.error_code, 2);
+        assert_eq!(err.error_str, format!("{} already exists, not replacing it without --force", dir.join("song.png").display()));
+        assert!(run(&["--dump-image", "--force", &files[0]]).0.is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directories_need_recursive() {
+        let dir = temp_dir("directories");
+        fs::create_dir(dir.join("album")).unwrap();
+        let files = write_files(&dir, &["album/song.mp3"], &mp3());
+        fs::write(dir.join("album/notes.txt"), "not music").unwrap();
+        let album = dir.join("album").display().to_string();
+
+        let err = Config::new(&args(&[&format!("{}/", album)]), "insignia").err().unwrap();
+        assert_eq!(err.error_code, 2);
+        assert_eq!(err.error_str, format!("{}/ is a directory. Directories aren't searched unless `--recursive` is given, pass the files inside it instead (e.g. `{}/*`)", album, album));
+
+        let config = Config::new(&args(&["--recursive", &album]), "insignia").ok().unwrap();
+        assert_eq!(config.files, files);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn no_default_print_prints_nothing_else() {
+        let dir = temp_dir("no-default-print");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+
+        let (result, out, warnings) = run(&["--no-default-print", "--title=Tune", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        assert_eq!(warnings, "");
+        assert_eq!(inner::read(&files[0]).unwrap().title(), Some("Tune"));
+
+        // Output that was asked for is still printed
+        let (_, out, _) = run(&["--no-default-print", "--print-field-json=title", &files[0]]);
+        assert_eq!(out, format!(r#"{{"path":"{}","field":"title","value":"Tune"}}"#, files[0]) + "\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn require_image_fails_files_without_a_cover() {
+        let dir = temp_dir("require-image");
+        let files = write_files(&dir, &["cover.mp3", "none.mp3"], &mp3());
+        write_tag(&files[0], |t| t.add_picture(front_cover(png(1, 1))));
+
+        assert!(run(&["--require-image", "--quiet", &files[0]]).0.is_ok());
+
+        let err = run(&["--require-image", "--quiet", &files[1]]).0.unwrap_err();
+        assert_eq!(err.error_code, 9);
+        assert_eq!(err.error_str, format!("`{}` has no cover", files[1]));
+
+        // Checked after the other options, so a cover that's added counts
+        let image = write_files(&dir, &["front.png"], &png(1, 1));
+        assert!(run(&["--require-image", "--quiet", &format!("--image={}", image[0]), &files[1]]).0.is_ok());
+
+        let err = run(&["--require-image", "--keep-going", "--quiet", "--clear=image", &files[0], &files[1]]).0.unwrap_err();
+        assert_eq!(err.error_code, 9);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn album_only_refuses_track_fields_on_many_files() {
+        let dir = temp_dir("album-only");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+        let parse = |a: &[&str]| Config::new(&args(a), "insignia").map(|_| ()).map_err(|e| (e.error_code, e.error_str));
+
+        assert_eq!(parse(&["--album-only", "--album=Record", "--year=2003", &files[0], &files[1]]), Ok(()));
+        assert_eq!(
+            parse(&["--album-only", "--album=Record", "--title=Tune", &files[0], &files[1]]),
+            Err((1, "Cannot set 'title' on 2 files with --album-only, since each track has its own".to_string())),
+        );
+        // A single file can take any field
+        assert_eq!(parse(&["--album-only", "--title=Tune", &files[0]]), Ok(()));
+        // As can files cut down to one by --limit
+        assert_eq!(parse(&["--album-only", "--limit=1", "--track=1", &files[0], &files[1]]), Ok(()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn id3_version_picks_the_version_written() {
+        let dir = temp_dir("id3-version");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        let version = || fs::read(&files[0]).unwrap()[3];
+
+        assert!(run(&["--quiet", "--id3-version=2.3", "--title=Tune", &files[0]]).0.is_ok());
+        assert_eq!(version(), 3);
+        assert_eq!(inner::read(&files[0]).unwrap().title(), Some("Tune"));
+
+        // Without it, tags are written as ID3v2.4, even ones that were ID3v2.3
+        assert!(run(&["--quiet", "--title=Tune", &files[0]]).0.is_ok());
+        assert_eq!(version(), 4);
+
+        assert!(run(&["--quiet", "--id3-version=2.3", "--title=Tune", &files[0]]).0.is_ok());
+        assert!(run(&["--quiet", "--id3-version=2.4", "--title=Tune", &files[0]]).0.is_ok());
+        assert_eq!(version(), 4);
+
+        assert_eq!(Config::new(&args(&["--id3-version=2.2", &files[0]]), "insignia").err().unwrap().error_code, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn image_base_resolves_relative_images() {
+        let dir = temp_dir("image-base");
+        fs::create_dir(dir.join("art")).unwrap();
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        let image = write_files(&dir, &["art/front.png"], &png(1, 1));
+        let base = format!("--image-base={}", dir.join("art").display());
+
+        assert!(run(&["--quiet", &base, "--image=front.png", &files[0]]).0.is_ok());
+        assert_eq!(inner::read(&files[0]).unwrap().album_cover().unwrap().data, &png(1, 1)[..]);
+
+        // Absolute paths don't need the base
+        assert!(run(&["--quiet", &base, &format!("--image={}", image[0]), &files[0]]).0.is_ok());
+
+        let err = Config::new(&args(&[&base, "--image=back.png", &files[0]]), "insignia").err().unwrap();
+        assert_eq!(err.error_code, 2);
+        assert_eq!(err.error_str, format!("File {} does not exist, is a broken symlink, or we may not have valid permissions", dir.join("art/back.png").display()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_cue_lists_a_track_per_file() {
+        let dir = temp_dir("export-cue");
+        let files = write_files(&dir, &["one.mp3", "two.wav.mp3", "three.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_album_artist("Band");
+            t.set_artist("Band feat. Singer");
+            t.set_album("Say \"Record\"");
+            t.set_title("Intro");
+            t.set_track(1);
+        });
+        write_tag(&files[1], |t| {
+            t.set_title("Tune");
+            t.set_track(5);
+        });
+
+        let (result, out, _) = run(&["--export-cue", &files[0], &files[1], &files[2]]);
+
+        assert!(result.is_ok());
+        assert_eq!(out, concat!(
+            "PERFORMER \"Band\"\n",
+            "TITLE \"Say 'Record'\"\n",
+            "FILE \"one.mp3\" MP3\n",
+            "  TRACK 01 AUDIO\n",
+            "    TITLE \"Intro\"\n",
+            "    PERFORMER \"Band feat. Singer\"\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"two.wav.mp3\" MP3\n",
+            "  TRACK 05 AUDIO\n",
+            "    TITLE \"Tune\"\n",
+            "    INDEX 01 00:00:00\n",
+            // No track number, so it's numbered by its position
+            "FILE \"three.mp3\" MP3\n",
+            "  TRACK 03 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_from_stdin_sets_a_field_on_every_file() {
+        let dir = temp_dir("set-from-stdin");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+        let code = |a: &[&str]| Config::new(&args(a), "insignia").err().unwrap().error_code;
+
+        let mut config = Config::new(&args(&["--quiet", "--set-from-stdin=artist", &files[0], &files[1]]), "insignia").ok().unwrap();
+        // The line ending that ends piped text isn't kept
+        config.fill_stdin(b"Band\r\n".to_vec());
+        config.exec_to(&mut Vec::new()).unwrap();
+
+        for f in &files {
+            assert_eq!(inner::read(f).unwrap().artist_str(), Some("Band"), "{}", f);
+        }
+
+        assert_eq!(code(&["--set-from-stdin=year", &files[0]]), 1);
+        assert_eq!(code(&["--set-from-stdin=mood", &files[0]]), 4);
+        assert_eq!(code(&["--set-from-stdin=artist", "--artist=Band", &files[0]]), 1);
+        assert_eq!(code(&["--set-from-stdin=artist", "--image=-", &files[0]]), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_field_presence_marks_each_field() {
+        let dir = temp_dir("print-field-presence");
+        let files = write_files(&dir, &["full.mp3", "a,b.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_title("Tune");
+            t.set_year(2003);
+            t.add_picture(front_cover(png(1, 1)));
+        });
+
+        let (result, out, _) = run(&["--print-field-presence", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(out, format!(
+            "disc  track  title  artist  album  albumartist  genre  composer  comment  image  year  file\n\
+             N     N      Y      N       N      N            N      N         N        Y      Y     {}\n\
+             N     N      N      N       N      N            N      N         N        N      N     {}\n",
+            files[0], files[1],
+        ));
+
+        // A path with a comma in it is quoted
+        let (_, out, _) = run(&["--print-field-presence", "--csv", &files[0], &files[1]]);
+        assert_eq!(out, format!(
+            "file,disc,track,title,artist,album,albumartist,genre,composer,comment,image,year\n{},N,N,Y,N,N,N,N,N,N,Y,Y\n\"{}\",N,N,N,N,N,N,N,N,N,N,N\n",
+            files[0], files[1],
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_hash_only_changes_with_the_tag() {
+        let dir = temp_dir("print-hash");
+        let files = write_files(&dir, &["a.mp3", "b.mp3", "c.mp3"], &mp3());
+        // Different audio, same tag
+        let mut longer = mp3();
+        longer.extend_from_slice(&mp3()[10..]);
+        fs::write(&files[1], &longer).unwrap();
+        for f in &files {
+            write_tag(f, |t| t.set_title("Tune"));
+        }
+        write_tag(&files[2], |t| t.set_title("Other"));
+
+        let hash = |f: &str| {
+            let (result, out, _) = run(&["--print-hash", f]);
+            assert!(result.is_ok());
+            let (hash, path) = out.trim_end().split_once("  ").unwrap();
+            assert_eq!(path, f);
+            hash.to_string()
+        };
+
+        // Hashes are compared between runs, so the same tag always hashes the same
+        assert_eq!(hash(&files[0]), "5a77722d31d3d32a");
+        assert_eq!(hash(&files[1]), "5a77722d31d3d32a");
+        assert_ne!(hash(&files[2]), "5a77722d31d3d32a");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fields_from_sets_each_files_own_fields() {
+        let dir = temp_dir("fields-from");
+        let files = write_files(&dir, &["a.mp3", "b.mp3", "c.mp3"], &mp3());
+        write_tag(&files[1], |t| t.set_album("Old"));
+        let manifest = dir.join("fields.tsv");
+        let fields = format!("--fields-from={}", manifest.display());
+        fs::write(&manifest, format!(
+            "# file\ttitle\ttrack\n{}\ttitle=One\ttrack=1\n\n{}\ttitle=Two\talbum=\t\n",
+            files[0], files[1],
+        )).unwrap();
+
+        // Fields given for every file apply along with each file's own, which win
+        let (result, _, _) = run(&["--quiet", &fields, "--artist=Band", "--title=Unnamed", &files[2]]);
+        assert!(result.is_ok());
+
+        let tags: Vec<_> = files.iter().map(|f| inner::read(f).unwrap()).collect();
+        assert_eq!((tags[0].title(), tags[0].track_number(), tags[0].artist_str()), (Some("One"), Some(1), Some("Band")));
+        assert_eq!((tags[1].title(), tags[1].album_title(), tags[1].artist_str()), (Some("Two"), None, Some("Band")));
+        assert_eq!((tags[2].title(), tags[2].artist_str()), (Some("Unnamed"), Some("Band")));
+
+        let error = |contents: String| {
+            fs::write(&manifest, contents).unwrap();
+            let err = Config::new(&args(&[&fields]), "insignia").err().unwrap();
+            (err.error_code, err.error_str)
+        };
+        assert_eq!(error(format!("{}\tmood=Happy\n", files[0])), (4, format!("Line 1 of fields file {}: 'mood' isn't a field", manifest.display())));
+        assert_eq!(error(format!("\n{}\ttitle\n", files[0])).0, 1);
+        assert_eq!(error(format!("{}\n{}\n", files[0], files[0])).0, 1);
+        assert_eq!(error(format!("{}\n", dir.join("missing.mp3").display())).0, 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_duplicates_by_tag_groups_matching_files() {
+        let dir = temp_dir("print-duplicates-by-tag");
+        let files = write_files(&dir, &["a.mp3", "b.mp3", "c.mp3", "d.mp3", "e.mp3", "untagged.mp3"], &mp3());
+        let tag = |f: &str, artist: &str, title: &str| write_tag(f, |t| {
+            t.set_artist(artist);
+            t.set_title(title);
+            t.set_album("Record");
+        });
+        tag(&files[0], "Band", "Tune");
+        tag(&files[1], "Other", "Song");
+        // Case and surrounding whitespace don't matter
+        tag(&files[2], "band ", "TUNE");
+        tag(&files[3], "Other", "Song");
+        tag(&files[4], "Band", "Another");
+
+        let (result, out, _) = run(&["--print-duplicates-by-tag", &files[0], &files[1], &files[2], &files[3], &files[4], &files[5]]);
+        assert!(result.is_ok());
+        assert_eq!(out, format!(
+            "Artist: Band, Title: Tune, Album: Record\n  {}\n  {}\n\nArtist: Other, Title: Song, Album: Record\n  {}\n  {}\n",
+            files[0], files[2], files[1], files[3],
+        ));
+
+        let (_, out, _) = run(&["--print-duplicates-by-tag", "--duplicate-field=album", &files[0], &files[4], &files[5]]);
+        assert_eq!(out, format!("Album: Record\n  {}\n  {}\n", files[0], files[4]));
+
+        let (result, out, warnings) = run(&["--print-duplicates-by-tag", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        assert_eq!(warnings, "No duplicates found\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn auto_cover_uses_a_cover_file_next_to_the_song() {
+        let dir = temp_dir("auto-cover");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+        let mut art = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(2, 2))
+            .write_to(&mut art, image::ImageOutputFormat::Jpeg(90)).unwrap();
+        fs::write(dir.join("folder.jpg"), &art).unwrap();
+        fs::write(dir.join("art.png"), png(3, 3)).unwrap();
+        write_tag(&files[1], |t| t.add_picture(front_cover(png(1, 1))));
+
+        let (result, _, warnings) = run(&["--auto-cover", &files[0], &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, "");
+        assert_eq!(inner::read(&files[0]).unwrap().album_cover().unwrap().data, &art[..]);
+        // A cover that's already there is left alone
+        assert_eq!(inner::read(&files[1]).unwrap().album_cover().unwrap().data, &png(1, 1)[..]);
+
+        // `--cover-name` replaces the usual names, and the first one found wins
+        let files = write_files(&dir, &["a.mp3"], &mp3());
+        let (result, _, _) = run(&["--auto-cover", "--cover-name=missing.jpg", "--cover-name=art.png", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(inner::read(&files[0]).unwrap().album_cover().unwrap().data, &png(3, 3)[..]);
+
+        let image = format!("--image={}", dir.join("art.png").display());
+        let error = run(&["--auto-cover", &image, &files[0]]).0.err().unwrap();
+        assert_eq!(error.error_code, 1);
+        assert!(error.error_str.contains("Cannot use 'auto-cover' and 'image' at the same time"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn field_max_values_keeps_the_first_values() {
+        let dir = temp_dir("field-max-values");
+        let files = write_files(&dir, &["song.mp3"], &mp3());
+        write_tag(&files[0], |t| {
+            t.set_artist("One\0Two\0Three\0One\0junk");
+            t.set_genre("Rock\0Pop\0Rock");
+            t.set_title("Tune");
+        });
+
+        let (result, _, warnings) = run(&["--field-max-values=2", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!(
+            "Dropped 3 value(s) from `TPE1` in `{0}`\nDropped 1 value(s) from `TCON` in `{0}`\n", files[0],
+        ));
+
+        let mut tag = inner::read(&files[0]).unwrap();
+        assert_eq!(inner::raw(tag.as_mut(), "TPE1").unwrap(), Some("One\0Two".to_string()));
+        assert_eq!(inner::raw(tag.as_mut(), "TCON").unwrap(), Some("Rock\0Pop".to_string()));
+        assert_eq!(tag.title(), Some("Tune"));
+
+        let error = run(&["--field-max-values=0", &files[0]]).0.err().unwrap();
+        assert_eq!(error.error_code, 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clear_related_removes_the_sort_frames() {
+        let dir = temp_dir("clear-related");
+        let files = write_files(&dir, &["a.mp3", "b.mp3"], &mp3());
+        for f in &files {
+            write_tag(f, |t| {
+                t.set_artist("The Band");
+                t.set_text("TSOP", "Band, The");
+                t.set_text("TSOT", "Tune");
+                t.set_title("Tune");
+            });
+        }
+
+        // Without the flag the sort frame is left behind
+        let (result, _, warnings) = run(&["--clear=artist", &files[0]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, "");
+        let mut tag = inner::read(&files[0]).unwrap();
+        assert_eq!(tag.artist_str(), None);
+        assert_eq!(inner::raw(tag.as_mut(), "TSOP").unwrap(), Some("Band, The".to_string()));
+
+        let (result, _, warnings) = run(&["--clear=artist", "--clear-related", &files[1]]);
+        assert!(result.is_ok());
+        assert_eq!(warnings, format!("Removed 1 artist sort order frame(s) from `{}`\n", files[1]));
+        let mut tag = inner::read(&files[1]).unwrap();
+        assert_eq!(tag.artist_str(), None);
+        assert_eq!(inner::raw(tag.as_mut(), "TSOP").unwrap(), None);
+        // Only the cleared field's sort frame goes
+        assert_eq!(inner::raw(tag.as_mut(), "TSOT").unwrap(), Some("Tune".to_string()));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,6 +1,7 @@
 use std::env;
 use std::process::exit;
 
+#[allow(clippy::single_component_path_imports)]
 use insignia;
 
 fn print_err(e: insignia::Error) -> ! {
@@ -0,0 +1,436 @@
+//! Reads the audio properties of a file. lofty doesn't expose these, so the stream headers are
+//! parsed here directly. Formats that aren't understood have every property left as `None`.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct Properties {
+    pub duration: Option<Duration>,
+    /// Average bitrate in kbps
+    pub bitrate: Option<u32>,
+    /// Sample rate in Hz
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    /// How many bytes the file's tags take up, padding included
+    pub tag_size: Option<usize>,
+}
+
+pub fn read(path: &str) -> io::Result<Properties> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+
+    let mut magic = [0; 4];
+    let magic_len = file.read(&mut magic)?;
+
+    let mut data = magic[..magic_len].to_vec();
+
+    // Everything needed from a FLAC file is in the metadata blocks at the start, so the audio
+    // frames after them are never read
+    if data == b"fLaC" {
+        read_flac_metadata(&mut file, &mut data)?;
+    } else {
+        file.read_to_end(&mut data)?;
+    }
+
+    let properties = if data.starts_with(b"fLaC") {
+        read_flac(&data, len)
+    } else if data.starts_with(b"OggS") {
+        read_ogg(&data)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        read_wav(&data)
+    } else if is_mp3(&data) {
+        read_mp3(&data)
+    } else {
+        None
+    };
+
+    let mut properties = properties.unwrap_or_default();
+    properties.tag_size = tag_size(&data);
+
+    Ok(properties)
+}
+
+/// Adds up the size of every tag in the file. Ogg and MP4 files aren't measured, since their tags
+/// are tangled up with the rest of the container.
+fn tag_size(data: &[u8]) -> Option<usize> {
+    if data.starts_with(b"fLaC") {
+        flac_tag_size(data)
+    } else if data.starts_with(b"OggS") {
+        None
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        wav_tag_size(data)
+    } else if data.get(4..8) == Some(b"ftyp") {
+        None
+    } else {
+        mp3_tag_size(data)
+    }
+}
+
+fn u32_be(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The size of an ID3v2 tag at the start of `data`, header and footer included
+fn id3v2_size(data: &[u8]) -> Option<usize> {
+    if !data.starts_with(b"ID3") {
+        return Some(0);
+    }
+
+    let size = data.get(6..10)?.iter().fold(0, |acc, b| (acc << 7) | usize::from(b & 0x7F));
+    let footer = if *data.get(5)? & 0x10 != 0 { 10 } else { 0 };
+
+    Some(10 + size + footer)
+}
+
+fn flac_tag_size(data: &[u8]) -> Option<usize> {
+    let mut size = 0;
+    let mut pos = 4;
+
+    // Every metadata block other than STREAMINFO is part of the tag
+    loop {
+        let header = *data.get(pos)?;
+        let len = (u32_be(data, pos)? & 0xFF_FFFF) as usize;
+
+        if header & 0x7F != 0 {
+            size += 4 + len;
+        }
+
+        pos += 4 + len;
+
+        if header & 0x80 != 0 { // The last metadata block
+            return Some(size);
+        }
+    }
+}
+
+fn wav_tag_size(data: &[u8]) -> Option<usize> {
+    let mut size = 0;
+    let mut pos = 12;
+
+    while let (Some(id), Some(len)) = (data.get(pos..pos + 4), u32_le(data, pos + 4)) {
+        let padded = len as usize + (len as usize & 1);
+
+        if id != b"fmt " && id != b"data" {
+            size += 8 + padded;
+        }
+
+        pos += 8 + padded;
+    }
+
+    Some(size)
+}
+
+fn mp3_tag_size(data: &[u8]) -> Option<usize> {
+    let mut size = id3v2_size(data)?;
+    let mut end = data.len();
+
+    // An ID3v1 tag takes up the last 128 bytes
+    if end >= 128 && data[end - 128..].starts_with(b"TAG") {
+        size += 128;
+        end -= 128;
+    }
+
+    // An APEv2 tag ends with a 32 byte footer, which records the tag's size minus its header
+    if end >= 32 && data[end - 32..].starts_with(b"APETAGEX") {
+        let len = u32_le(data, end - 20)? as usize;
+        let header = if u32_le(data, end - 12)? & 0x8000_0000 != 0 { 32 } else { 0 };
+        size += len + header;
+    }
+
+    Some(size)
+}
+
+/// The average bitrate (in kbps) of `len` bytes played over `duration`
+fn average_bitrate(len: usize, duration: Duration) -> Option<u32> {
+    let secs = duration.as_secs_f64();
+
+    if secs > 0.0 {
+        Some((len as f64 * 8.0 / secs / 1000.0).round() as u32)
+    } else {
+        None
+    }
+}
+
+fn u32_le(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u16_le(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+/// Reads the metadata blocks of a FLAC file onto the end of `data`, stopping after the last one
+fn read_flac_metadata(file: &mut File, data: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+        data.extend_from_slice(&header);
+
+        let len = u32::from_be_bytes(header) & 0xFF_FFFF;
+        file.take(u64::from(len)).read_to_end(data)?;
+
+        if header[0] & 0x80 != 0 { // The last metadata block
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the properties of a FLAC file from its metadata blocks. `len` is the length of the whole
+/// file, which is used for the bitrate.
+fn read_flac(data: &[u8], len: usize) -> Option<Properties> {
+    // The STREAMINFO block always comes first, right after the block header
+    let info = data.get(8..42)?;
+    let packed = u64::from_be_bytes(info[10..18].try_into().ok()?);
+
+    let sample_rate = (packed >> 44) as u32;
+    let channels = ((packed >> 41) & 0x7) as u8 + 1;
+    let total_samples = packed & 0xF_FFFF_FFFF;
+
+    let duration = if sample_rate > 0 && total_samples > 0 {
+        Some(Duration::from_secs_f64(total_samples as f64 / f64::from(sample_rate)))
+    } else {
+        None
+    };
+
+    Some(Properties {
+        duration,
+        bitrate: duration.and_then(|d| average_bitrate(len, d)),
+        sample_rate: Some(sample_rate),
+        channels: Some(channels),
+        tag_size: None,
+    })
+}
+
+fn read_ogg(data: &[u8]) -> Option<Properties> {
+    // The first packet starts after the page header and its segment table
+    let packet = data.get(27 + usize::from(*data.get(26)?)..)?;
+
+    // The last page's granule position is the total number of samples in the stream
+    let last_page = data.windows(4).rposition(|w| w == b"OggS")?;
+    let granule = u64::from_le_bytes(data.get(last_page + 6..last_page + 14)?.try_into().ok()?);
+
+    let (channels, sample_rate, samples) = if packet.starts_with(b"\x01vorbis") {
+        (*packet.get(11)?, u32_le(packet, 12)?, granule)
+    } else if packet.starts_with(b"OpusHead") {
+        // Opus always plays back at 48kHz, after skipping the pre-skip samples
+        let pre_skip = u64::from(u16_le(packet, 10)?);
+        (*packet.get(9)?, 48000, granule.saturating_sub(pre_skip))
+    } else {
+        return None;
+    };
+
+    let duration = if sample_rate > 0 {
+        Some(Duration::from_secs_f64(samples as f64 / f64::from(sample_rate)))
+    } else {
+        None
+    };
+
+    Some(Properties {
+        duration,
+        bitrate: duration.and_then(|d| average_bitrate(data.len(), d)),
+        sample_rate: Some(sample_rate),
+        channels: Some(channels),
+        tag_size: None,
+    })
+}
+
+fn read_wav(data: &[u8]) -> Option<Properties> {
+    let mut properties = Properties::default();
+    let mut byte_rate = 0;
+    let mut pos = 12;
+
+    while let (Some(id), Some(size)) = (data.get(pos..pos + 4), u32_le(data, pos + 4)) {
+        let body = pos + 8;
+
+        match id {
+            b"fmt " => {
+                properties.channels = Some(u16_le(data, body + 2)? as u8);
+                properties.sample_rate = Some(u32_le(data, body + 4)?);
+                byte_rate = u32_le(data, body + 8)?;
+                properties.bitrate = byte_rate.checked_mul(8).map(|b| b / 1000);
+            }
+            b"data" if byte_rate > 0 => {
+                properties.duration = Some(Duration::from_secs_f64(f64::from(size) / f64::from(byte_rate)));
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even length
+        pos = body + size as usize + (size as usize & 1);
+    }
+
+    Some(properties)
+}
+
+/// The header of a single MPEG audio frame
+struct FrameHeader {
+    len: usize,
+    samples: u32,
+    sample_rate: u32,
+    channels: u8,
+}
+
+fn parse_frame_header(header: &[u8]) -> Option<FrameHeader> {
+    if header.len() < 4 || header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let version = (header[1] >> 3) & 0x3; // 0: MPEG 2.5, 2: MPEG 2, 3: MPEG 1
+    let layer = (header[1] >> 1) & 0x3; // 1: Layer III, 2: Layer II, 3: Layer I
+    let bitrate_index = usize::from(header[2] >> 4);
+    let sample_rate_index = usize::from((header[2] >> 2) & 0x3);
+    let padding = u32::from((header[2] >> 1) & 0x1);
+    let channels = if header[3] >> 6 == 3 { 1 } else { 2 };
+
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    const V1_L1: [u32; 15] = [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448];
+    const V1_L2: [u32; 15] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384];
+    const V1_L3: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const V2_L1: [u32; 15] = [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256];
+    const V2_L2_L3: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+    let bitrate = match (version, layer) {
+        (3, 3) => V1_L1,
+        (3, 2) => V1_L2,
+        (3, _) => V1_L3,
+        (_, 3) => V2_L1,
+        _ => V2_L2_L3,
+    }[bitrate_index] * 1000;
+
+    let sample_rate = [44100, 48000, 32000][sample_rate_index] >> match version {
+        3 => 0,
+        2 => 1,
+        _ => 2,
+    };
+
+    let (samples, len) = match layer {
+        3 => (384, (12 * bitrate / sample_rate + padding) * 4),
+        1 if version != 3 => (576, 72 * bitrate / sample_rate + padding),
+        _ => (1152, 144 * bitrate / sample_rate + padding),
+    };
+
+    Some(FrameHeader { len: len as usize, samples, sample_rate, channels })
+}
+
+/// Whether `data` is an MP3, going by an ID3v2 tag or an MPEG frame at the very start. Containers
+/// that aren't recognised, like AIFF and APE, would otherwise have MPEG frames found in their audio.
+fn is_mp3(data: &[u8]) -> bool {
+    data.starts_with(b"ID3") || parse_frame_header(data).is_some()
+}
+
+fn read_mp3(data: &[u8]) -> Option<Properties> {
+    // Skip over an ID3v2 tag
+    let mut pos = id3v2_size(data)?;
+
+    // Find the first frame
+    pos += data.get(pos..)?.windows(4).position(|w| parse_frame_header(w).is_some())?;
+    let first = parse_frame_header(&data[pos..])?;
+
+    let mut samples: u64 = 0;
+    let mut audio_len = 0;
+
+    while let Some(frame) = data.get(pos..).and_then(parse_frame_header) {
+        if frame.len == 0 {
+            break;
+        }
+
+        samples += u64::from(frame.samples);
+        audio_len += frame.len;
+        pos += frame.len;
+    }
+
+    let duration = Duration::from_secs_f64(samples as f64 / f64::from(first.sample_rate));
+
+    Some(Properties {
+        duration: Some(duration),
+        bitrate: average_bitrate(audio_len, duration),
+        sample_rate: Some(first.sample_rate),
+        channels: Some(first.channels),
+        tag_size: None,
+    })
+}
+
+/// The parts of a file that hold its audio, leaving out every tag, so the audio can be compared
+/// before and after a tag is written. `None` when the audio can't be found.
+pub fn audio_slices(data: &[u8]) -> Option<Vec<&[u8]>> {
+    if data.starts_with(b"fLaC") {
+        // Everything after the last metadata block
+        let mut pos = 4;
+
+        loop {
+            let header = *data.get(pos)?;
+            pos += 4 + (u32_be(data, pos)? & 0xFF_FFFF) as usize;
+
+            if header & 0x80 != 0 {
+                return Some(vec![data.get(pos..)?]);
+            }
+        }
+    } else if data.starts_with(b"OggS") {
+        // Header packets, the comments included, are on pages with a granule position of 0
+        let mut slices = Vec::new();
+        let mut pos = 0;
+
+        while data.get(pos..pos + 4) == Some(b"OggS") {
+            let granule = u64::from_le_bytes(data.get(pos + 6..pos + 14)?.try_into().ok()?);
+            let segments = usize::from(*data.get(pos + 26)?);
+            let body = pos + 27 + segments;
+            let len: usize = data.get(pos + 27..body)?.iter().map(|s| usize::from(*s)).sum();
+
+            if granule != 0 {
+                slices.push(data.get(body..body + len)?);
+            }
+
+            pos = body + len;
+        }
+
+        Some(slices)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        let mut pos = 12;
+
+        while let (Some(id), Some(len)) = (data.get(pos..pos + 4), u32_le(data, pos + 4)) {
+            if id == b"data" {
+                return Some(vec![data.get(pos + 8..pos + 8 + len as usize)?]);
+            }
+
+            pos += 8 + len as usize + (len as usize & 1);
+        }
+
+        None
+    } else if data.get(4..8) == Some(b"ftyp") {
+        // The audio is in the top level `mdat` atoms
+        let mut slices = Vec::new();
+        let mut pos = 0;
+
+        while let (Some(len), Some(id)) = (u32_be(data, pos), data.get(pos + 4..pos + 8)) {
+            let (header, len) = match len {
+                0 => (8, data.len() - pos),
+                1 => (16, u64::from_be_bytes(data.get(pos + 8..pos + 16)?.try_into().ok()?) as usize),
+                _ => (8, len as usize),
+            };
+
+            if len < header {
+                return None;
+            }
+
+            if id == b"mdat" {
+                slices.push(data.get(pos + header..pos + len)?);
+            }
+
+            pos += len;
+        }
+
+        Some(slices)
+    } else {
+        // An MP3, between the ID3v2 tag at the start and the tags at the end
+        let start = id3v2_size(data)?;
+        let end = data.len().checked_sub(mp3_tag_size(data)? - start)?;
+
+        Some(vec![data.get(start..end)?])
+    }
+}
@@ -0,0 +1,66 @@
+//! Repairs ID3v2 tags that were appended to the end of an MP3 instead of placed at the start. The
+//! ID3v2.4 spec allows this, but lofty and most players only look at the start of the file. Only
+//! appended tags with a footer can be found, since without one there's no way to tell where the
+//! tag begins.
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+/// Where an ID3v2 tag appended to the end of `data` is, going by its footer. The footer may be
+/// followed by an ID3v1 tag.
+fn appended_id3(data: &[u8]) -> Option<Range<usize>> {
+    let mut end = data.len();
+
+    if end >= 128 && data[end - 128..].starts_with(b"TAG") {
+        end -= 128;
+    }
+
+    let footer = data.get(end.checked_sub(10)?..end)?;
+
+    if !footer.starts_with(b"3DI") {
+        return None;
+    }
+
+    let size = footer[6..10].iter().fold(0, |acc, b| (acc << 7) | usize::from(b & 0x7F));
+    let start = end.checked_sub(20 + size)?;
+
+    if data[start..].starts_with(b"ID3") {
+        Some(start..end)
+    } else {
+        None
+    }
+}
+
+/// What `repair` found
+pub enum Repair {
+    /// The tag was already at the start, or there was no tag at the end
+    NotNeeded,
+    /// The tag was moved from the end to the start
+    Moved,
+    /// There's a tag at both ends, so it isn't clear which one to keep
+    Conflict,
+}
+
+/// Moves an ID3v2 tag at the end of the file at `path` to the start
+pub fn repair(path: &str) -> io::Result<Repair> {
+    let data = fs::read(path)?;
+
+    let range = match appended_id3(&data) {
+        Some(r) => r,
+        None => return Ok(Repair::NotNeeded),
+    };
+
+    if data.starts_with(b"ID3") {
+        return Ok(Repair::Conflict);
+    }
+
+    // The footer is dropped, since id3 doesn't expect one on a tag at the start
+    let mut repaired = data[range.start..range.end - 10].to_vec();
+    repaired[5] &= !0x10;
+    repaired.extend_from_slice(&data[..range.start]);
+    repaired.extend_from_slice(&data[range.end..]);
+    fs::write(path, repaired)?;
+
+    Ok(Repair::Moved)
+}